@@ -0,0 +1,251 @@
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+use crate::*;
+
+/// The modules that changed between two refreshes of a CachedModules
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModuleDiff {
+    pub loaded: Vec<Module>,
+    pub unloaded: Vec<Module>,
+}
+
+impl ModuleDiff {
+    fn between(old: &[ModuleInfo], new: &[ModuleInfo]) -> Self {
+        let loaded = new.iter().filter(|m| !old.iter().any(|o| o.module == m.module)).map(|m| m.module.clone()).collect();
+        let unloaded = old.iter().filter(|o| !new.iter().any(|m| m.module == o.module)).map(|m| m.module.clone()).collect();
+        Self { loaded, unloaded }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.loaded.is_empty() && self.unloaded.is_empty()
+    }
+}
+
+struct ModuleCache {
+    modules: Vec<ModuleInfo>,
+    fetched: Instant,
+}
+
+/// Wraps a ModuleList and caches its module list, so looking up modules does not walk the
+/// loader list of the process every time. The list is fetched again when it is older than the
+/// time to live or when refresh_modules is called. Without a time to live the list is only
+/// fetched again by refresh_modules.
+pub struct CachedModules<M> {
+    inner: M,
+    ttl: Option<Duration>,
+    cache: Mutex<Option<ModuleCache>>,
+    subscribers: Mutex<Vec<Sender<ModuleDiff>>>,
+}
+
+impl<M: ModuleList> CachedModules<M> {
+    pub fn new(inner: M) -> Self {
+        Self { inner, ttl: None, cache: Mutex::new(None), subscribers: Mutex::new(Vec::new()) }
+    }
+
+    /// Fetches the module list again on the first lookup after ttl has elapsed
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Returns a receiver for the changes found by every refresh after this call.
+    /// Refreshes that find no changes are not sent
+    pub fn subscribe(&self) -> Receiver<ModuleDiff> {
+        let (sender, receiver) = channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Fetches the module list from the inner type and returns the modules that were loaded
+    /// and unloaded since the last fetch. The first fetch does not report any changes
+    pub fn refresh_modules(&self) -> ModuleDiff {
+        let mut cache = self.cache.lock().unwrap();
+        self.refresh(&mut cache)
+    }
+
+    /// Drops the cached list so the next lookup fetches it again
+    pub fn invalidate(&self) {
+        *self.cache.lock().unwrap() = None;
+    }
+
+    /// Returns a reference to the inner type
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    /// Consumes the wrapper and returns the inner type
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    fn refresh(&self, cache: &mut Option<ModuleCache>) -> ModuleDiff {
+        let modules = self.inner.get_module_info_list();
+        let diff = match cache {
+            Some(old) => ModuleDiff::between(&old.modules, &modules),
+            None => ModuleDiff::default(),
+        };
+        *cache = Some(ModuleCache { modules, fetched: Instant::now() });
+
+        if !diff.is_empty() {
+            self.subscribers.lock().unwrap().retain(|s| s.send(diff.clone()).is_ok());
+        }
+        diff
+    }
+
+    fn with_modules<R>(&self, f: impl FnOnce(&[ModuleInfo]) -> R) -> R {
+        let mut cache = self.cache.lock().unwrap();
+        let expired = match (&*cache, self.ttl) {
+            (None, _) => true,
+            (Some(c), Some(ttl)) => c.fetched.elapsed() >= ttl,
+            (Some(_), None) => false,
+        };
+        if expired {
+            self.refresh(&mut cache);
+        }
+        f(&cache.as_ref().unwrap().modules)
+    }
+}
+
+impl<M: ModuleList> ModuleList for CachedModules<M> {
+    fn get_module_list(&self) -> Vec<Module> {
+        self.with_modules(|modules| modules.iter().map(|m| m.module.clone()).collect())
+    }
+
+    fn get_module(&self, name: &str) -> Option<Module> {
+        self.with_modules(|modules| modules.iter().find(|m| m.matches_name(name)).map(|m| m.module.clone()))
+    }
+
+    fn get_main_module(&self) -> Module {
+        self.with_modules(|modules| modules.iter().find(|m| m.is_main).map(|m| m.module.clone()))
+            .unwrap_or_else(|| self.inner.get_main_module())
+    }
+
+    fn get_module_info_list(&self) -> Vec<ModuleInfo> {
+        self.with_modules(|modules| modules.to_vec())
+    }
+}
+
+impl<M: ModuleList + MemoryRead> MemoryRead for CachedModules<M> {
+    fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        self.inner.try_read_bytes_into(address, buffer)
+    }
+}
+
+impl<M: ModuleList + MemoryWrite> MemoryWrite for CachedModules<M> {
+    fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
+        self.inner.try_write_bytes(address, buffer)
+    }
+}
+
+impl<M: ModuleList + ProcessInfo> ProcessInfo for CachedModules<M> {
+    fn process_name(&self) -> String {
+        self.inner.process_name()
+    }
+
+    fn peb_base_address(&self) -> u64 {
+        self.inner.peb_base_address()
+    }
+
+    fn pid(&self) -> u32 {
+        self.inner.pid()
+    }
+}
+
+impl<M: ModuleList + MemoryAllocate> MemoryAllocate for CachedModules<M> {
+    fn allocate(&self, size: u64, protection: MemoryProtection) -> Result<u64, MemoryAllocateError> {
+        self.inner.allocate(size, protection)
+    }
+
+    fn free(&self, base: u64, size: u64) -> Result<(), MemoryAllocateError> {
+        self.inner.free(base, size)
+    }
+}
+
+impl<M: ModuleList + MemoryProtect> MemoryProtect for CachedModules<M> {
+    fn set_protection(&self, range: MemoryRange, protection: MemoryProtection) -> Result<MemoryProtection, MemoryProtectError> {
+        self.inner.set_protection(range, protection)
+    }
+}
+
+impl<M: ModuleList + MemoryRegions> MemoryRegions for CachedModules<M> {
+    fn query_region(&self, address: u64) -> Option<MemoryRegion> {
+        self.inner.query_region(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Counts how many times the module list is walked
+    struct CountingModules {
+        process: MockProcess,
+        walks: AtomicU32,
+    }
+
+    impl ModuleList for CountingModules {
+        fn get_module_list(&self) -> Vec<Module> {
+            self.walks.fetch_add(1, Ordering::SeqCst);
+            self.process.get_module_list()
+        }
+
+        fn get_main_module(&self) -> Module {
+            self.process.get_main_module()
+        }
+    }
+
+    fn process() -> CountingModules {
+        let process = MockProcess::new();
+        process.add_module("game.exe", 0x140000000, vec![0; 0x1000], MemoryProtection::READONLY);
+        process.add_module("ntdll.dll", 0x7FF800000000, vec![0; 0x1000], MemoryProtection::READONLY);
+        CountingModules { process, walks: AtomicU32::new(0) }
+    }
+
+    #[test]
+    fn test_cache() {
+        let cached = CachedModules::new(process());
+        for _ in 0..10 {
+            assert_eq!(cached.get_module("NTDLL.DLL").unwrap().base, 0x7FF800000000);
+        }
+        assert_eq!(cached.get_main_module().name, "game.exe");
+        assert_eq!(cached.inner().walks.load(Ordering::SeqCst), 1);
+
+        cached.invalidate();
+        cached.get_module_list();
+        assert_eq!(cached.inner().walks.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_ttl() {
+        let cached = CachedModules::new(process()).ttl(Duration::from_millis(20));
+        cached.get_module_list();
+        cached.get_module_list();
+        assert_eq!(cached.inner().walks.load(Ordering::SeqCst), 1);
+
+        std::thread::sleep(Duration::from_millis(30));
+        cached.get_module_list();
+        assert_eq!(cached.inner().walks.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_diffs() {
+        let cached = CachedModules::new(process());
+        let events = cached.subscribe();
+        assert!(cached.refresh_modules().is_empty());
+
+        let loaded = cached.inner().process.add_module("user32.dll", 0x7FF900000000, vec![0; 0x1000], MemoryProtection::READONLY);
+        assert!(cached.get_module("user32.dll").is_none(), "lookups use the cached list until a refresh");
+        cached.inner().process.remove_module("ntdll.dll");
+
+        let diff = cached.refresh_modules();
+        assert_eq!(diff.loaded, [loaded]);
+        assert_eq!(diff.unloaded.len(), 1);
+        assert_eq!(diff.unloaded[0].name, "ntdll.dll");
+        assert!(cached.get_module("user32.dll").is_some());
+
+        assert!(cached.refresh_modules().is_empty());
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), [diff]);
+    }
+}
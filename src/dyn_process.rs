@@ -0,0 +1,373 @@
+use crate::*;
+
+/// An object safe process that can always be read and queried for modules and process information.
+/// Other capabilities are optional and can be queried at runtime, so a backend chosen at runtime
+/// can be used as a `Box<dyn Process>`.
+///
+/// `dyn Process` also implements MemoryWrite, MemoryAllocate, MemoryProtect and MemoryRegions by
+/// forwarding to the optional capabilities, which fail if the capability is not supported. This
+/// makes every Ext helper usable on a `dyn Process`.
+pub trait Process: MemoryRead + ModuleList + ProcessInfo {
+    /// Returns the process as a MemoryWrite if it can be written to
+    fn as_writer(&self) -> Option<&dyn MemoryWrite> {
+        None
+    }
+
+    /// Returns the process as a MemoryAllocate if memory can be allocated in it
+    fn as_allocator(&self) -> Option<&dyn MemoryAllocate> {
+        None
+    }
+
+    /// Returns the process as a MemoryProtect if the protection of its memory can be changed
+    fn as_protector(&self) -> Option<&dyn MemoryProtect> {
+        None
+    }
+
+    /// Returns the process as a MemoryRegions if its memory regions can be queried
+    fn as_regions(&self) -> Option<&dyn MemoryRegions> {
+        None
+    }
+}
+
+impl<P: Process + ?Sized> Process for Box<P> {
+    fn as_writer(&self) -> Option<&dyn MemoryWrite> {
+        (**self).as_writer()
+    }
+
+    fn as_allocator(&self) -> Option<&dyn MemoryAllocate> {
+        (**self).as_allocator()
+    }
+
+    fn as_protector(&self) -> Option<&dyn MemoryProtect> {
+        (**self).as_protector()
+    }
+
+    fn as_regions(&self) -> Option<&dyn MemoryRegions> {
+        (**self).as_regions()
+    }
+}
+
+impl Process for MockProcess {
+    fn as_writer(&self) -> Option<&dyn MemoryWrite> {
+        Some(self)
+    }
+
+    fn as_allocator(&self) -> Option<&dyn MemoryAllocate> {
+        Some(self)
+    }
+
+    fn as_protector(&self) -> Option<&dyn MemoryProtect> {
+        Some(self)
+    }
+
+    fn as_regions(&self) -> Option<&dyn MemoryRegions> {
+        Some(self)
+    }
+}
+
+impl MemoryReadExt for dyn Process {}
+
+impl MemoryWrite for dyn Process {
+    fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
+        self.as_writer()?.try_write_bytes(address, buffer)
+    }
+}
+
+impl MemoryWriteExt for dyn Process {}
+
+impl MemoryAllocate for dyn Process {
+    fn allocate(&self, size: u64, protection: MemoryProtection) -> Result<u64, MemoryAllocateError> {
+        self.as_allocator()
+            .ok_or_else(|| MemoryAllocateError::Message("process does not support allocating memory".to_string()))?
+            .allocate(size, protection)
+    }
+
+    fn free(&self, base: u64, size: u64) -> Result<(), MemoryAllocateError> {
+        self.as_allocator()
+            .ok_or_else(|| MemoryAllocateError::Message("process does not support allocating memory".to_string()))?
+            .free(base, size)
+    }
+}
+
+impl MemoryAllocateExt for dyn Process {}
+
+impl MemoryProtect for dyn Process {
+    fn set_protection(&self, range: MemoryRange, protection: MemoryProtection) -> Result<MemoryProtection, MemoryProtectError> {
+        self.as_protector()
+            .ok_or_else(|| MemoryProtectError::Message("process does not support changing memory protection".to_string()))?
+            .set_protection(range, protection)
+    }
+}
+
+impl MemoryProtectExt for dyn Process {}
+
+impl MemoryRegions for dyn Process {
+    fn query_region(&self, address: u64) -> Option<MemoryRegion> {
+        self.as_regions()?.query_region(address)
+    }
+}
+
+/// Implements Process for any process type with the capabilities chosen at construction
+pub struct DynProcess<P> {
+    inner: P,
+    writer: Option<fn(&P) -> &dyn MemoryWrite>,
+    allocator: Option<fn(&P) -> &dyn MemoryAllocate>,
+    protector: Option<fn(&P) -> &dyn MemoryProtect>,
+    regions: Option<fn(&P) -> &dyn MemoryRegions>,
+}
+
+impl<P: MemoryRead + ModuleList + ProcessInfo> DynProcess<P> {
+    /// Creates a process that can only be read from and queried
+    pub fn new(inner: P) -> Self {
+        Self { inner, writer: None, allocator: None, protector: None, regions: None }
+    }
+
+    /// Exposes MemoryWrite through as_writer
+    pub fn writer(mut self) -> Self
+        where P: MemoryWrite {
+        self.writer = Some(|p| p);
+        self
+    }
+
+    /// Exposes MemoryAllocate through as_allocator
+    pub fn allocator(mut self) -> Self
+        where P: MemoryAllocate {
+        self.allocator = Some(|p| p);
+        self
+    }
+
+    /// Exposes MemoryProtect through as_protector
+    pub fn protector(mut self) -> Self
+        where P: MemoryProtect {
+        self.protector = Some(|p| p);
+        self
+    }
+
+    /// Exposes MemoryRegions through as_regions
+    pub fn regions(mut self) -> Self
+        where P: MemoryRegions {
+        self.regions = Some(|p| p);
+        self
+    }
+
+    /// Returns a reference to the inner type
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Consumes the wrapper and returns the inner type
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+}
+
+impl<P: MemoryRead> MemoryRead for DynProcess<P> {
+    fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        self.inner.try_read_bytes_into(address, buffer)
+    }
+}
+
+impl<P: ModuleList> ModuleList for DynProcess<P> {
+    fn get_module_list(&self) -> Vec<Module> {
+        self.inner.get_module_list()
+    }
+
+    fn get_module(&self, name: &str) -> Option<Module> {
+        self.inner.get_module(name)
+    }
+
+    fn get_main_module(&self) -> Module {
+        self.inner.get_main_module()
+    }
+
+    fn get_module_info_list(&self) -> Vec<ModuleInfo> {
+        self.inner.get_module_info_list()
+    }
+}
+
+impl<P: ProcessInfo> ProcessInfo for DynProcess<P> {
+    fn process_name(&self) -> String {
+        self.inner.process_name()
+    }
+
+    fn peb_base_address(&self) -> u64 {
+        self.inner.peb_base_address()
+    }
+
+    fn pid(&self) -> u32 {
+        self.inner.pid()
+    }
+}
+
+impl<P: MemoryRead + ModuleList + ProcessInfo> Process for DynProcess<P> {
+    fn as_writer(&self) -> Option<&dyn MemoryWrite> {
+        self.writer.map(|f| f(&self.inner))
+    }
+
+    fn as_allocator(&self) -> Option<&dyn MemoryAllocate> {
+        self.allocator.map(|f| f(&self.inner))
+    }
+
+    fn as_protector(&self) -> Option<&dyn MemoryProtect> {
+        self.protector.map(|f| f(&self.inner))
+    }
+
+    fn as_regions(&self) -> Option<&dyn MemoryRegions> {
+        self.regions.map(|f| f(&self.inner))
+    }
+}
+
+/// An object safe version of ProcessAttach that returns type erased processes,
+/// so the backend can be chosen at runtime
+pub trait DynProcessAttach {
+    /// Attaches to a process of name process_name. If no process is found None is returned
+    fn attach_dyn(&self, process_name: &str) -> Option<Box<dyn Process>>;
+
+    /// Attaches to a process by a pid. If the pid does not exist, this will return None
+    fn attach_pid_dyn(&self, pid: u32) -> Option<Box<dyn Process>>;
+}
+
+/// Implements DynProcessAttach for a GetContext backend. Each process owns a clone of the
+/// backend and is converted into a `Box<dyn Process>` by wrap, which chooses its capabilities
+pub struct DynAttacher<T: GetContext + 'static> {
+    api: T,
+    wrap: fn(AttachedProcess<'static, T>) -> Box<dyn Process>,
+}
+
+impl<T: GetContext + Clone + 'static> DynAttacher<T> {
+    pub fn new(api: T, wrap: fn(AttachedProcess<'static, T>) -> Box<dyn Process>) -> Self {
+        Self { api, wrap }
+    }
+}
+
+impl<T: GetContext + Clone + 'static> DynProcessAttach for DynAttacher<T> {
+    fn attach_dyn(&self, process_name: &str) -> Option<Box<dyn Process>> {
+        self.api.clone().attach_into(process_name).map(self.wrap)
+    }
+
+    fn attach_pid_dyn(&self, pid: u32) -> Option<Box<dyn Process>> {
+        self.api.clone().attach_into_pid(pid).map(self.wrap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mock() -> MockProcess {
+        let process = MockProcess::new().with_process_name("game.exe").with_pid(5);
+        process.add_module("game.exe", 0x140000000, vec![0; 0x2000], MemoryProtection::READWRITE);
+        process
+    }
+
+    /// A backend that can only read from one mock process
+    #[derive(Clone)]
+    struct ReadOnlyApi;
+
+    impl GetContext for ReadOnlyApi {
+        type Context = u32;
+
+        fn get_context_from_name(&self, process_name: &str) -> Option<u32> {
+            (process_name == "game.exe").then_some(5)
+        }
+
+        fn get_context_from_pid(&self, pid: u32) -> Option<u32> {
+            (pid == 5).then_some(pid)
+        }
+
+        fn get_current_context(&self) -> u32 {
+            0
+        }
+    }
+
+    impl MemoryReadPid for ReadOnlyApi {
+        fn try_read_bytes_into_pid(&self, _pid: &u32, _address: u64, buffer: &mut [u8]) -> Option<()> {
+            buffer.fill(0xCC);
+            Some(())
+        }
+    }
+
+    impl ModuleListPid for ReadOnlyApi {
+        fn get_module_list(&self, _pid: &u32) -> Vec<Module> {
+            vec![self.get_main_module(&5)]
+        }
+
+        fn get_main_module(&self, _pid: &u32) -> Module {
+            Module { name: "game.exe".to_string(), base: 0x140000000, size: 0x2000 }
+        }
+    }
+
+    impl ProcessInfoPid for ReadOnlyApi {
+        fn process_name(&self, _pid: &u32) -> String {
+            "game.exe".to_string()
+        }
+
+        fn peb_base_address(&self, _pid: &u32) -> u64 {
+            0
+        }
+
+        fn pid(&self, pid: &u32) -> u32 {
+            *pid
+        }
+    }
+
+    struct MockAttach;
+
+    impl DynProcessAttach for MockAttach {
+        fn attach_dyn(&self, process_name: &str) -> Option<Box<dyn Process>> {
+            (process_name == "game.exe").then(|| Box::new(mock()) as Box<dyn Process>)
+        }
+
+        fn attach_pid_dyn(&self, pid: u32) -> Option<Box<dyn Process>> {
+            (pid == 5).then(|| Box::new(mock()) as Box<dyn Process>)
+        }
+    }
+
+    fn backend(name: &str) -> Box<dyn DynProcessAttach> {
+        match name {
+            "mock" => Box::new(MockAttach),
+            _ => Box::new(DynAttacher::new(ReadOnlyApi, |p| Box::new(DynProcess::new(p)))),
+        }
+    }
+
+    #[test]
+    fn test_full_capabilities() {
+        let process = backend("mock").attach_dyn("game.exe").unwrap();
+        let base = process.get_main_module().base;
+
+        process.write(base, &0x1234u32);
+        assert_eq!(process.read::<u32>(base), 0x1234);
+        assert_eq!(process.pid(), 5);
+
+        let allocation = process.allocate_guarded(0x1000, MemoryProtection::READWRITE).unwrap();
+        allocation.write(0, &1u64);
+        drop(allocation);
+
+        let guard = process.with_protection(base..base + 0x1000, MemoryProtection::READONLY).unwrap();
+        assert!(process.try_write(base, &0u32).is_none());
+        drop(guard);
+        assert_eq!(process.query_region(base).unwrap().protection, MemoryProtection::READWRITE);
+    }
+
+    #[test]
+    fn test_read_only() {
+        let process = backend("remote").attach_pid_dyn(5).unwrap();
+        assert!(backend("remote").attach_dyn("other.exe").is_none());
+
+        assert_eq!(process.read::<u16>(0x1000), 0xCCCC);
+        assert_eq!(process.get_module("GAME.EXE").unwrap().base, 0x140000000);
+        assert!(process.as_writer().is_none());
+        assert!(process.try_write(0x1000, &0u8).is_none());
+        assert!(process.allocate(0x1000, MemoryProtection::READWRITE).is_err());
+        assert!(process.with_protection(0..0x1000, MemoryProtection::READONLY).is_err());
+    }
+
+    #[test]
+    fn test_capability_selection() {
+        let process: Box<dyn Process> = Box::new(DynProcess::new(mock()).writer().regions());
+        assert!(process.as_writer().is_some());
+        assert!(process.as_regions().is_some());
+        assert!(process.as_allocator().is_none());
+        assert!(process.as_protector().is_none());
+    }
+}
@@ -0,0 +1,264 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use crate::*;
+use crate::forward::forward_process_traits;
+
+/// Wraps a MemoryRead or MemoryWrite implementation and makes reads and writes fail on a
+/// deterministic schedule. Every random decision is made from a seeded generator, so the
+/// same seed and the same sequence of calls always produces the same faults.
+///
+/// Faults are configured with the builder methods. A call fails if any of the configured
+/// conditions match. Successful reads can also have their bytes corrupted.
+pub struct FaultInjector<M> {
+    inner: M,
+    seed: u64,
+    counter: AtomicU64,
+    calls: AtomicU64,
+    faults: AtomicU64,
+    corruptions: AtomicU64,
+    created: Instant,
+
+    failure_rate: f64,
+    fail_ranges: Vec<MemoryRange>,
+    fail_every: Option<u64>,
+    fail_after: Option<Duration>,
+    corruption_rate: f64,
+    latency: Option<Duration>,
+    reads: bool,
+    writes: bool,
+}
+
+impl<M> FaultInjector<M> {
+    /// Creates a new fault injector with a seed that injects no faults until configured
+    pub fn new(inner: M, seed: u64) -> Self {
+        Self {
+            inner,
+            seed,
+            counter: AtomicU64::new(0),
+            calls: AtomicU64::new(0),
+            faults: AtomicU64::new(0),
+            corruptions: AtomicU64::new(0),
+            created: Instant::now(),
+            failure_rate: 0.0,
+            fail_ranges: Vec::new(),
+            fail_every: None,
+            fail_after: None,
+            corruption_rate: 0.0,
+            latency: None,
+            reads: true,
+            writes: true,
+        }
+    }
+
+    /// Fails a fraction of calls, where 0.0 never fails and 1.0 always fails
+    pub fn failure_rate(mut self, rate: f64) -> Self {
+        self.failure_rate = rate;
+        self
+    }
+
+    /// Fails any call that touches the memory range. May be called more than once
+    pub fn fail_range(mut self, range: MemoryRange) -> Self {
+        self.fail_ranges.push(range);
+        self
+    }
+
+    /// Fails every nth call, starting with call number n
+    pub fn fail_every(mut self, n: u64) -> Self {
+        assert_ne!(n, 0, "fail_every must be greater than zero");
+        self.fail_every = Some(n);
+        self
+    }
+
+    /// Fails every call made after the duration has passed since the injector was created
+    pub fn fail_after(mut self, duration: Duration) -> Self {
+        self.fail_after = Some(duration);
+        self
+    }
+
+    /// Flips one random bit in the bytes returned by a fraction of successful reads
+    pub fn corruption_rate(mut self, rate: f64) -> Self {
+        self.corruption_rate = rate;
+        self
+    }
+
+    /// Sleeps for the duration before every call
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Sets whether faults are injected into reads
+    pub fn fault_reads(mut self, enabled: bool) -> Self {
+        self.reads = enabled;
+        self
+    }
+
+    /// Sets whether faults are injected into writes
+    pub fn fault_writes(mut self, enabled: bool) -> Self {
+        self.writes = enabled;
+        self
+    }
+
+    /// Returns the number of reads and writes made through the injector
+    pub fn calls(&self) -> u64 {
+        self.calls.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of calls that were failed by the injector
+    pub fn injected_faults(&self) -> u64 {
+        self.faults.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of reads that had their bytes corrupted
+    pub fn injected_corruptions(&self) -> u64 {
+        self.corruptions.load(Ordering::Relaxed)
+    }
+
+    /// Returns a reference to the inner type
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    /// Consumes the injector and returns the inner type
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    /// Returns the next random number from the seeded generator (splitmix64)
+    fn next_random(&self) -> u64 {
+        let n = self.counter.fetch_add(1, Ordering::Relaxed);
+        let mut z = self.seed.wrapping_add(n.wrapping_add(1).wrapping_mul(0x9E3779B97F4A7C15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns true with the probability of rate
+    fn chance(&self, rate: f64) -> bool {
+        if rate <= 0.0 {
+            return false;
+        }
+        ((self.next_random() >> 11) as f64 / (1u64 << 53) as f64) < rate
+    }
+
+    /// Records a call and returns true if it should fail
+    fn should_fail(&self, address: u64, len: usize, enabled: bool) -> bool {
+        let call = self.calls.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(latency) = self.latency {
+            std::thread::sleep(latency);
+        }
+        if !enabled {
+            return false;
+        }
+
+        let end = address.saturating_add(len as u64);
+        // non short-circuiting so the generator advances the same amount on every call
+        let fail = self.fail_every.is_some_and(|n| call.is_multiple_of(n))
+            | self.fail_ranges.iter().any(|r| address < r.end && r.start < end)
+            | self.fail_after.is_some_and(|d| self.created.elapsed() >= d)
+            | self.chance(self.failure_rate);
+
+        if fail {
+            self.faults.fetch_add(1, Ordering::Relaxed);
+        }
+        fail
+    }
+}
+
+impl<M: MemoryRead> MemoryRead for FaultInjector<M> {
+    fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        if self.should_fail(address, buffer.len(), self.reads) {
+            return None;
+        }
+        self.inner.try_read_bytes_into(address, buffer)?;
+
+        if self.reads && !buffer.is_empty() && self.chance(self.corruption_rate) {
+            let bit = self.next_random() % (buffer.len() as u64 * 8);
+            buffer[(bit / 8) as usize] ^= 1 << (bit % 8);
+            self.corruptions.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(())
+    }
+}
+
+impl<M: MemoryWrite> MemoryWrite for FaultInjector<M> {
+    fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
+        if self.should_fail(address, buffer.len(), self.writes) {
+            return None;
+        }
+        self.inner.try_write_bytes(address, buffer)
+    }
+}
+
+forward_process_traits!(FaultInjector.inner: ModuleList, ProcessInfo, MemoryAllocate, MemoryProtect, MemoryRegions);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer() -> MemoryBuffer<Vec<u8>> {
+        MemoryBuffer::new(0x1000, vec![0u8; 0x1000])
+    }
+
+    fn schedule(injector: &FaultInjector<MemoryBuffer<Vec<u8>>>) -> Vec<bool> {
+        (0..100).map(|i| injector.try_read::<u32>(0x1000 + i * 4).is_some()).collect()
+    }
+
+    #[test]
+    fn test_seeded_schedule_is_deterministic() {
+        let a = schedule(&FaultInjector::new(buffer(), 42).failure_rate(0.3));
+        let b = schedule(&FaultInjector::new(buffer(), 42).failure_rate(0.3));
+        let c = schedule(&FaultInjector::new(buffer(), 43).failure_rate(0.3));
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        let failures = a.iter().filter(|ok| !**ok).count();
+        assert!((10..50).contains(&failures), "{} failures", failures);
+    }
+
+    #[test]
+    fn test_fail_every() {
+        let injector = FaultInjector::new(buffer(), 0).fail_every(3);
+        let results = schedule(&injector);
+
+        assert!(results.iter().enumerate().all(|(i, ok)| *ok == ((i + 1) % 3 != 0)));
+        assert_eq!(injector.calls(), 100);
+        assert_eq!(injector.injected_faults(), 33);
+    }
+
+    #[test]
+    fn test_fail_range_partial_read() {
+        let injector = FaultInjector::new(buffer(), 0).fail_range(0x1100..0x1101);
+        let mut buf = [0u8; 0x400];
+
+        assert!(injector.try_read_bytes_into(0x1000, &mut buf).is_none());
+        assert_eq!(injector.try_read_bytes_into_chunked_fallible::<0x100>(0x1000, &mut buf), Some(0x300));
+        assert!(injector.try_write_bytes(0x10FF, &[0, 0]).is_none());
+        assert!(injector.try_write_bytes(0x1101, &[0, 0]).is_some());
+    }
+
+    #[test]
+    fn test_fail_after() {
+        let injector = FaultInjector::new(buffer(), 0).fail_after(Duration::ZERO);
+        assert!(injector.try_read::<u8>(0x1000).is_none());
+
+        let injector = FaultInjector::new(buffer(), 0).fail_after(Duration::from_secs(3600));
+        assert!(injector.try_read::<u8>(0x1000).is_some());
+    }
+
+    #[test]
+    fn test_corruption() {
+        let injector = FaultInjector::new(buffer(), 7).corruption_rate(1.0);
+        let bytes = injector.try_read_bytes(0x1000, 0x10).unwrap();
+
+        assert_eq!(bytes.iter().map(|b| b.count_ones()).sum::<u32>(), 1);
+        assert_eq!(injector.injected_corruptions(), 1);
+    }
+
+    #[test]
+    fn test_fault_writes_only() {
+        let injector = FaultInjector::new(buffer(), 0).failure_rate(1.0).fault_reads(false);
+        assert!(injector.try_read::<u8>(0x1000).is_some());
+        assert!(injector.try_write(0x1000, &0u8).is_none());
+    }
+}
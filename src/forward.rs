@@ -0,0 +1,69 @@
+/// Implements the process traits of a wrapper with a single generic parameter by forwarding
+/// every method to one of its fields. Only the listed traits are implemented, each bounded on
+/// the inner type implementing it.
+macro_rules! forward_process_traits {
+    ($wrapper:ident.$field:ident: $($trait:ident),+ $(,)?) => {
+        $($crate::forward::forward_process_traits!(@impl $wrapper $field $trait);)+
+    };
+    (@impl $wrapper:ident $field:ident ModuleList) => {
+        impl<M: $crate::ModuleList> $crate::ModuleList for $wrapper<M> {
+            fn get_module_list(&self) -> Vec<$crate::Module> {
+                self.$field.get_module_list()
+            }
+
+            fn get_module(&self, name: &str) -> Option<$crate::Module> {
+                self.$field.get_module(name)
+            }
+
+            fn get_main_module(&self) -> $crate::Module {
+                self.$field.get_main_module()
+            }
+
+            fn get_module_info_list(&self) -> Vec<$crate::ModuleInfo> {
+                self.$field.get_module_info_list()
+            }
+        }
+    };
+    (@impl $wrapper:ident $field:ident ProcessInfo) => {
+        impl<M: $crate::ProcessInfo> $crate::ProcessInfo for $wrapper<M> {
+            fn process_name(&self) -> String {
+                self.$field.process_name()
+            }
+
+            fn peb_base_address(&self) -> u64 {
+                self.$field.peb_base_address()
+            }
+
+            fn pid(&self) -> u32 {
+                self.$field.pid()
+            }
+        }
+    };
+    (@impl $wrapper:ident $field:ident MemoryAllocate) => {
+        impl<M: $crate::MemoryAllocate> $crate::MemoryAllocate for $wrapper<M> {
+            fn allocate(&self, size: u64, protection: $crate::MemoryProtection) -> Result<u64, $crate::MemoryAllocateError> {
+                self.$field.allocate(size, protection)
+            }
+
+            fn free(&self, base: u64, size: u64) -> Result<(), $crate::MemoryAllocateError> {
+                self.$field.free(base, size)
+            }
+        }
+    };
+    (@impl $wrapper:ident $field:ident MemoryProtect) => {
+        impl<M: $crate::MemoryProtect> $crate::MemoryProtect for $wrapper<M> {
+            fn set_protection(&self, range: $crate::MemoryRange, protection: $crate::MemoryProtection) -> Result<$crate::MemoryProtection, $crate::MemoryProtectError> {
+                self.$field.set_protection(range, protection)
+            }
+        }
+    };
+    (@impl $wrapper:ident $field:ident MemoryRegions) => {
+        impl<M: $crate::MemoryRegions> $crate::MemoryRegions for $wrapper<M> {
+            fn query_region(&self, address: u64) -> Option<$crate::MemoryRegion> {
+                self.$field.query_region(address)
+            }
+        }
+    };
+}
+
+pub(crate) use forward_process_traits;
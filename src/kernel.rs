@@ -1,4 +1,18 @@
-use crate::{MemoryRead, MemoryWrite};
+use crate::{MemoryBuffer, MemoryRead, MemoryWrite};
+
+mod cached_mappings;
+mod dump_file;
+mod page_table;
+mod physical_map;
+mod tlb_cache;
+mod translation;
+
+pub use cached_mappings::*;
+pub use dump_file::*;
+pub use page_table::*;
+pub use physical_map::*;
+pub use tlb_cache::*;
+pub use translation::*;
 
 /// Represents a type that can load and unload a kernel exploit
 pub trait LoadDriver {
@@ -43,8 +57,7 @@ impl<'a, T: PhysicalMemoryRead + TranslatePhysical> VirtualMemoryReader<'a, T> {
 
 impl<'a, T: PhysicalMemoryRead + TranslatePhysical> MemoryRead for VirtualMemoryReader<'a, T> {
     fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
-        let physical_address = self.0.physical_address(address)?;
-        self.0.try_read_bytes_physical_into(physical_address, buffer)
+        read_virtual(self.0, address, buffer)
     }
 }
 
@@ -106,15 +119,13 @@ impl<'a, T: PhysicalMemoryRead + PhysicalMemoryWrite + TranslatePhysical> Virtua
 
 impl<'a, T: PhysicalMemoryRead + PhysicalMemoryWrite + TranslatePhysical> MemoryRead for VirtualMemory<'a, T> {
     fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
-        let physical_address = self.0.physical_address(address)?;
-        self.0.try_read_bytes_physical_into(physical_address, buffer)
+        read_virtual(self.0, address, buffer)
     }
 }
 
 impl<'a, T: PhysicalMemoryRead + PhysicalMemoryWrite + TranslatePhysical> MemoryWrite for VirtualMemory<'a, T> {
     fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
-        let physical_address = self.0.physical_address(address)?;
-        self.0.try_write_bytes_physical(physical_address, buffer)
+        write_virtual(self.0, address, buffer)
     }
 }
 
@@ -128,8 +139,7 @@ impl<'a, T: PhysicalMemoryWrite + TranslatePhysical> VirtualMemoryWriter<'a, T>
 
 impl<'a, T: PhysicalMemoryWrite + TranslatePhysical> MemoryWrite for VirtualMemoryWriter<'a, T> {
     fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
-        let physical_address = self.0.physical_address(address)?;
-        self.0.try_write_bytes_physical(physical_address, buffer)
+        write_virtual(self.0, address, buffer)
     }
 }
 
@@ -208,13 +218,15 @@ pub trait MapPhysical {
     }
 }
 
+/// Maps and unmaps the physical memory around every read. This impl has nowhere to keep
+/// mappings between calls, so wrap the type in CachedMappings to keep recently used pages mapped
 impl<T: MapPhysical + KernelMemoryRead> PhysicalMemoryRead for T {
     fn try_read_bytes_physical_into(&self, physical_address: u64, buffer: &mut [u8]) -> Option<()> {
         unsafe {
             let map = self.map_io_space(physical_address, buffer.len())?;
-            self.try_read_bytes_into(map, buffer)?;
+            let result = self.try_read_bytes_into(map, buffer);
             self.unmap_io_space(map, buffer.len())?;
-            Some(())
+            result
         }
     }
 }
@@ -223,15 +235,67 @@ impl<T: MapPhysical + KernelMemoryWrite> PhysicalMemoryWrite for T {
     fn try_write_bytes_physical(&self, physical_address: u64, buffer: &[u8]) -> Option<()> {
         unsafe {
             let map = self.map_io_space(physical_address, buffer.len())?;
-            self.try_write_bytes(map, buffer)?;
+            let result = self.try_write_bytes(map, buffer);
             self.unmap_io_space(map, buffer.len())?;
-            Some(())
+            result
         }
     }
 }
 
 pub trait TranslatePhysical {
     fn physical_address(&self, virtual_address: u64) -> Option<u64>;
+
+    /// Translates virtual_address and returns the page it is in, or why it could not be translated.
+    /// By default this uses physical_address and assumes 4KiB pages, so translators that walk
+    /// page tables should override this
+    fn translate(&self, virtual_address: u64) -> Result<Translation, TranslationError> {
+        let physical_address = self.physical_address(virtual_address).ok_or(TranslationError::Unmapped)?;
+        Ok(Translation { physical_address, page_size: PAGE_SIZE, flags: None, entries: Vec::new() })
+    }
+
+    /// Returns the physical address of virtual_address and the number of bytes after it that are
+    /// physically contiguous, which is the rest of the page it is in
+    fn physical_range(&self, virtual_address: u64) -> Option<(u64, u64)> {
+        let translation = self.translate(virtual_address).ok()?;
+        Some((translation.physical_address, translation.remaining_in_page()))
+    }
+}
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Translates every page in a virtual range and returns the physical address of each physically
+/// contiguous piece with its range in the buffer. Returns None if any page is not mapped
+fn physical_chunks<T: TranslatePhysical>(api: &T, address: u64, len: usize) -> Option<Vec<(u64, core::ops::Range<usize>)>> {
+    let mut chunks: Vec<(u64, core::ops::Range<usize>)> = Vec::new();
+    let mut offset = 0;
+    while offset < len {
+        let (physical_address, contiguous) = api.physical_range(address.checked_add(offset as u64)?)?;
+        let end = len.min(offset.saturating_add(contiguous.max(1) as usize));
+
+        // merge pieces that happen to be next to each other in physical memory
+        match chunks.last_mut() {
+            Some((last, range)) if *last + range.len() as u64 == physical_address => range.end = end,
+            _ => chunks.push((physical_address, offset..end)),
+        }
+        offset = end;
+    }
+    Some(chunks)
+}
+
+fn read_virtual<T: PhysicalMemoryRead + TranslatePhysical>(api: &T, address: u64, buffer: &mut [u8]) -> Option<()> {
+    for (physical_address, range) in physical_chunks(api, address, buffer.len())? {
+        api.try_read_bytes_physical_into(physical_address, &mut buffer[range])?;
+    }
+    Some(())
+}
+
+/// Every page is translated before anything is written, so a write to a partly unmapped range
+/// does not write anything. A physical write that fails can still leave earlier pieces written
+fn write_virtual<T: PhysicalMemoryWrite + TranslatePhysical>(api: &T, address: u64, buffer: &[u8]) -> Option<()> {
+    for (physical_address, range) in physical_chunks(api, address, buffer.len())? {
+        api.try_write_bytes_physical(physical_address, &buffer[range])?;
+    }
+    Some(())
 }
 
 /// A struct describing a buffer of mapped physical memory generated by the MapPhysical trait
@@ -245,13 +309,18 @@ impl<'a, T: MapPhysical + KernelMemoryRead + KernelMemoryWrite> MappedPhysicalMe
     pub(crate) unsafe fn new(api: &'a T, base: u64, size: usize) -> Self {
         Self { api, base, size }
     }
+
+    /// Returns true if len bytes at address, which is an offset into the mapping, are inside the mapping
+    fn contains(&self, address: u64, len: usize) -> bool {
+        address.checked_add(len as u64).is_some_and(|end| end <= self.size as u64)
+    }
 }
 
 impl<'a, T: MapPhysical + KernelMemoryRead + KernelMemoryWrite> crate::MemoryRead
 for MappedPhysicalMemory<'a, T>
 {
     fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
-        if address + buffer.len() as u64 > self.base + self.size as u64 {
+        if !self.contains(address, buffer.len()) {
             return None;
         }
         self.api
@@ -263,7 +332,7 @@ impl<'a, T: MapPhysical + KernelMemoryRead + KernelMemoryWrite> crate::MemoryWri
 for MappedPhysicalMemory<'a, T>
 {
     fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
-        if address + buffer.len() as u64 > self.base + self.size as u64 {
+        if !self.contains(address, buffer.len()) {
             return None;
         }
         self.api.try_write_bytes(self.base as u64 + address, buffer)
@@ -277,3 +346,173 @@ for MappedPhysicalMemory<'a, T>
         unsafe { self.api.unmap_io_space(self.base, self.size).unwrap() }
     }
 }
+
+impl<B: AsRef<[u8]>> PhysicalMemoryRead for MemoryBuffer<B> {
+    fn try_read_bytes_physical_into(&self, physical_address: u64, buffer: &mut [u8]) -> Option<()> {
+        self.try_read_bytes_into(physical_address, buffer)
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> PhysicalMemoryWrite for MemoryBuffer<B> {
+    fn try_write_bytes_physical(&self, physical_address: u64, buffer: &[u8]) -> Option<()> {
+        self.try_write_bytes(physical_address, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::cached_mappings::tests::MockDriver;
+    use super::page_table::tests::PageTableBuilder;
+    use crate::MemoryBuffer;
+
+    /// Counts reads of frames so tests can check that contiguous pages are merged
+    struct CountingPhysical {
+        memory: MemoryBuffer<Vec<u8>>,
+        reads: core::cell::Cell<u32>,
+    }
+
+    impl PhysicalMemoryRead for CountingPhysical {
+        fn try_read_bytes_physical_into(&self, physical_address: u64, buffer: &mut [u8]) -> Option<()> {
+            // page tables are below the frames and are not counted
+            if physical_address >= 0x10_0000 {
+                self.reads.set(self.reads.get() + 1);
+            }
+            self.memory.try_read_bytes_physical_into(physical_address, buffer)
+        }
+    }
+
+    impl PhysicalMemoryWrite for CountingPhysical {
+        fn try_write_bytes_physical(&self, physical_address: u64, buffer: &[u8]) -> Option<()> {
+            self.memory.try_write_bytes_physical(physical_address, buffer)
+        }
+    }
+
+    fn next_random(state: &mut u64) -> u64 {
+        *state = state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    const VIRTUAL_BASE: u64 = 0x7FF0_0000_0000;
+    const PAGES: u64 = 16;
+
+    /// Maps 16 virtual pages to shuffled physical pages with page 9 left unmapped, followed by a 2MiB page.
+    /// Returns the walker and a flat reference of the virtual memory, where None is unmapped
+    fn address_space(seed: u64) -> (PageTableWalker<CountingPhysical>, Vec<Option<u8>>) {
+        let mut state = seed;
+        let mut builder = PageTableBuilder::new(0x60_0000, page_table::PagingMode::FourLevel);
+        let mut frames: Vec<u64> = (0..PAGES).map(|i| 0x10_0000 + i * 0x1000).collect();
+        for i in (1..frames.len()).rev() {
+            frames.swap(i, next_random(&mut state) as usize % (i + 1));
+        }
+        // keep pages 2 and 3 physically contiguous so they are read in one piece
+        match frames.iter().position(|f| *f == frames[2] + 0x1000) {
+            Some(next) => frames.swap(3, next),
+            None => frames.swap(2, 3),
+        }
+
+        for page in (0..PAGES).filter(|p| *p != 9) {
+            builder.map_page(VIRTUAL_BASE + page * 0x1000, frames[page as usize]);
+        }
+        builder.map(VIRTUAL_BASE + 0x20_0000, 0x40_0000 | 1, 0x20_0000);
+
+        // page tables are below 0x10_0000, so only the frames are filled with data
+        let mut data = vec![0u8; 0x50_0000];
+        data.iter_mut().for_each(|b| *b = next_random(&mut state) as u8);
+        builder.memory.try_write_bytes(0x10_0000, &data).unwrap();
+
+        let mut reference = vec![None; 0x40_0000];
+        for page in (0..PAGES).filter(|p| *p != 9) {
+            let bytes = builder.memory.try_read_bytes(frames[page as usize], 0x1000).unwrap();
+            let offset = (page * 0x1000) as usize;
+            reference[offset..offset + 0x1000].iter_mut().zip(bytes).for_each(|(r, b)| *r = Some(b));
+        }
+        let bytes = builder.memory.try_read_bytes(0x40_0000, 0x20_0000).unwrap();
+        reference[0x20_0000..].iter_mut().zip(bytes).for_each(|(r, b)| *r = Some(b));
+
+        let physical = CountingPhysical { memory: builder.memory, reads: Default::default() };
+        (PageTableWalker::new(physical, builder.dtb), reference)
+    }
+
+    fn random_range(state: &mut u64) -> (u64, usize) {
+        let offset = match next_random(state) % 3 {
+            0 => next_random(state) % (PAGES * 0x1000),
+            1 => 0x20_0000 - 0x800 + next_random(state) % 0x1000,
+            _ => 0x20_0000 + next_random(state) % 0x20_0000,
+        };
+        (offset, 1 + (next_random(state) % 0x2800) as usize)
+    }
+
+    fn expected(reference: &[Option<u8>], offset: u64, len: usize) -> Option<Vec<u8>> {
+        reference.get(offset as usize..offset as usize + len)?.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_reads_match_reference() {
+        for seed in 0..4 {
+            let (walker, reference) = address_space(seed);
+            let reader = walker.virtual_reader();
+            let mut state = seed ^ 0xABCDEF;
+            for _ in 0..300 {
+                let (offset, len) = random_range(&mut state);
+                let read = reader.try_read_bytes(VIRTUAL_BASE + offset, len);
+                assert_eq!(read, expected(&reference, offset, len), "read of {:#X} bytes at offset {:#X}", len, offset);
+            }
+        }
+    }
+
+    #[test]
+    fn test_writes_match_reference() {
+        let (walker, mut reference) = address_space(7);
+        let memory = walker.virtual_memory();
+        let mut state = 99;
+        for i in 0..200 {
+            let (offset, len) = random_range(&mut state);
+            let data = vec![i as u8; len];
+            let result = memory.try_write_bytes(VIRTUAL_BASE + offset, &data);
+
+            let target = reference.get_mut(offset as usize..offset as usize + len);
+            match target {
+                Some(target) if target.iter().all(|b| b.is_some()) => {
+                    assert!(result.is_some());
+                    target.iter_mut().for_each(|b| *b = Some(i as u8));
+                }
+                _ => assert!(result.is_none()),
+            }
+        }
+
+        for page in 0..PAGES + 0x200 {
+            let offset = page * 0x1000;
+            assert_eq!(memory.try_read_bytes(VIRTUAL_BASE + offset, 0x1000), expected(&reference, offset, 0x1000));
+        }
+    }
+
+    #[test]
+    fn test_contiguous_pieces_are_merged() {
+        let (walker, _) = address_space(3);
+        walker.virtual_reader().try_read_bytes(VIRTUAL_BASE + 0x2800, 0x1000).unwrap();
+        assert_eq!(walker.inner().reads.get(), 1);
+
+        walker.virtual_reader().try_read_bytes(VIRTUAL_BASE + 0x20_0800, 0x10_0000).unwrap();
+        assert_eq!(walker.inner().reads.get(), 2);
+    }
+
+    #[test]
+    fn test_mapped_physical_bounds() {
+        let driver = MockDriver::new(0x10000);
+        {
+            let mapped = driver.map_physical(0x8000, 0x100).unwrap();
+            let mut buffer = [0u8; 0x10];
+            // offsets are relative to the mapping, so the whole mapping is readable
+            assert!(mapped.try_read_bytes_into(0xF0, &mut buffer).is_some());
+            assert!(mapped.try_read_bytes_into(0xF8, &mut buffer).is_none());
+            assert!(mapped.try_read_bytes_into(u64::MAX - 4, &mut buffer).is_none());
+            assert!(mapped.try_write_bytes(0xF0, &buffer).is_some());
+            assert!(mapped.try_write_bytes(0x100, &buffer[..1]).is_none());
+        }
+        assert_eq!((driver.map_count(), driver.unmap_count()), (1, 1));
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use crate::*;
+
+/// The size of the physical windows CachedMappings maps by default
+pub const DEFAULT_MAPPING_WINDOW: usize = 0x10000;
+/// The number of windows CachedMappings keeps mapped by default
+pub const DEFAULT_MAPPING_CAPACITY: usize = 64;
+
+struct Window {
+    virtual_address: u64,
+    last_used: u64,
+}
+
+struct MappingState {
+    /// Mapped windows keyed by their physical base
+    windows: HashMap<u64, Window>,
+    clock: u64,
+}
+
+/// Wraps a MapPhysical driver and keeps recently used windows of physical memory mapped, so
+/// physical reads and writes do not map and unmap the memory every time like the blanket
+/// PhysicalMemoryRead impl does.
+///
+/// Windows are aligned to their size. When more than the capacity are mapped the least recently
+/// used window is unmapped, and every window is unmapped when the cache is dropped. If a whole
+/// window cannot be mapped, for example at the end of physical memory, only the accessed bytes
+/// are mapped for that access
+pub struct CachedMappings<T: MapPhysical + KernelMemoryRead> {
+    api: T,
+    window_size: usize,
+    capacity: usize,
+    state: Mutex<MappingState>,
+}
+
+impl<T: MapPhysical + KernelMemoryRead> CachedMappings<T> {
+    pub fn new(api: T) -> Self {
+        Self {
+            api,
+            window_size: DEFAULT_MAPPING_WINDOW,
+            capacity: DEFAULT_MAPPING_CAPACITY,
+            state: Mutex::new(MappingState { windows: HashMap::new(), clock: 0 }),
+        }
+    }
+
+    /// Sets the size of each mapped window. The size is rounded up to a power of two of at least a page
+    pub fn window_size(mut self, size: usize) -> Self {
+        self.flush();
+        self.window_size = size.max(0x1000).next_power_of_two();
+        self
+    }
+
+    /// Sets the maximum number of windows that stay mapped
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.flush();
+        self.capacity = capacity;
+        self
+    }
+
+    /// Returns the number of windows that are mapped
+    pub fn mapped_windows(&self) -> usize {
+        self.state.lock().unwrap().windows.len()
+    }
+
+    /// Unmaps every window
+    pub fn flush(&self) {
+        let mut state = self.state.lock().unwrap();
+        for (_, window) in state.windows.drain() {
+            unsafe { self.api.unmap_io_space(window.virtual_address, self.window_size) };
+        }
+    }
+
+    /// Returns a reference to the inner driver
+    pub fn inner(&self) -> &T {
+        &self.api
+    }
+
+    /// Calls f with the mapped virtual address of each piece of the physical range and the range
+    /// of the buffer it covers. The lock is held during f so windows are not unmapped while in use
+    fn for_each_mapping(&self, physical_address: u64, len: usize, mut f: impl FnMut(u64, core::ops::Range<usize>) -> Option<()>) -> Option<()> {
+        let mut state = self.state.lock().unwrap();
+        let window_size = self.window_size as u64;
+        let mut offset = 0;
+
+        while offset < len {
+            let address = physical_address.checked_add(offset as u64)?;
+            let base = address & !(window_size - 1);
+            let size = ((window_size - (address - base)) as usize).min(len - offset);
+            let range = offset..offset + size;
+
+            match self.window(&mut state, base) {
+                Some(window) => f(window + (address - base), range)?,
+                None => unsafe {
+                    let map = self.api.map_io_space(address, size)?;
+                    let result = f(map, range);
+                    self.api.unmap_io_space(map, size)?;
+                    result?
+                },
+            }
+            offset += size;
+        }
+        Some(())
+    }
+
+    /// Returns the virtual address of the window at base, mapping it and evicting the least
+    /// recently used window if needed
+    fn window(&self, state: &mut MappingState, base: u64) -> Option<u64> {
+        if self.capacity == 0 {
+            return None;
+        }
+        state.clock += 1;
+        let clock = state.clock;
+        if let Some(window) = state.windows.get_mut(&base) {
+            window.last_used = clock;
+            return Some(window.virtual_address);
+        }
+
+        // evict before mapping so no more than capacity windows are ever mapped at once
+        if state.windows.len() >= self.capacity {
+            let oldest = state.windows.iter().min_by_key(|(_, w)| w.last_used).map(|(&base, _)| base);
+            if let Some(window) = oldest.and_then(|base| state.windows.remove(&base)) {
+                unsafe { self.api.unmap_io_space(window.virtual_address, self.window_size) };
+            }
+        }
+        let virtual_address = unsafe { self.api.map_io_space(base, self.window_size)? };
+        state.windows.insert(base, Window { virtual_address, last_used: clock });
+        Some(virtual_address)
+    }
+}
+
+impl<T: MapPhysical + KernelMemoryRead> PhysicalMemoryRead for CachedMappings<T> {
+    fn try_read_bytes_physical_into(&self, physical_address: u64, buffer: &mut [u8]) -> Option<()> {
+        self.for_each_mapping(physical_address, buffer.len(), |map, range| {
+            self.api.try_read_bytes_into(map, &mut buffer[range])
+        })
+    }
+}
+
+impl<T: MapPhysical + KernelMemoryRead + KernelMemoryWrite> PhysicalMemoryWrite for CachedMappings<T> {
+    fn try_write_bytes_physical(&self, physical_address: u64, buffer: &[u8]) -> Option<()> {
+        self.for_each_mapping(physical_address, buffer.len(), |map, range| {
+            self.api.try_write_bytes(map, &buffer[range])
+        })
+    }
+}
+
+impl<T: MapPhysical + KernelMemoryRead + TranslatePhysical> TranslatePhysical for CachedMappings<T> {
+    fn physical_address(&self, virtual_address: u64) -> Option<u64> {
+        self.api.physical_address(virtual_address)
+    }
+
+    fn translate(&self, virtual_address: u64) -> Result<Translation, TranslationError> {
+        self.api.translate(virtual_address)
+    }
+}
+
+impl<T: MapPhysical + KernelMemoryRead> Drop for CachedMappings<T> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+    /// A driver that maps a buffer of physical memory at increasing fake virtual addresses and
+    /// counts the map and unmap calls
+    pub(crate) struct MockDriver {
+        pub physical: Mutex<Vec<u8>>,
+        /// virtual base -> (physical base, size)
+        pub mappings: Arc<Mutex<HashMap<u64, (u64, usize)>>>,
+        pub maps: Arc<AtomicU32>,
+        pub unmaps: Arc<AtomicU32>,
+        /// The most mappings that existed at the same time
+        pub peak: AtomicUsize,
+        /// Makes every read and write of mapped memory fail
+        pub fail_access: AtomicBool,
+        next: Mutex<u64>,
+    }
+
+    impl MockDriver {
+        pub fn new(size: usize) -> Self {
+            let physical = (0..size).map(|i| (i / 0x1000) as u8 ^ i as u8).collect();
+            Self {
+                physical: Mutex::new(physical),
+                mappings: Arc::default(),
+                maps: Arc::default(),
+                unmaps: Arc::default(),
+                peak: AtomicUsize::new(0),
+                fail_access: AtomicBool::new(false),
+                next: Mutex::new(0xFFFF_8000_0000_0000),
+            }
+        }
+
+        pub fn map_count(&self) -> u32 {
+            self.maps.load(Ordering::SeqCst)
+        }
+
+        pub fn unmap_count(&self) -> u32 {
+            self.unmaps.load(Ordering::SeqCst)
+        }
+
+        /// Returns the physical address a mapped virtual range refers to
+        fn resolve(&self, address: u64, len: usize) -> Option<usize> {
+            if self.fail_access.load(Ordering::SeqCst) {
+                return None;
+            }
+            let mappings = self.mappings.lock().unwrap();
+            let (&base, &(physical, size)) = mappings.iter().find(|(&base, &(_, size))| address >= base && address < base + size as u64)?;
+            (address + len as u64 <= base + size as u64).then_some((physical + address - base) as usize)
+        }
+    }
+
+    impl MapPhysical for MockDriver {
+        unsafe fn map_io_space(&self, physical_address: u64, size: usize) -> Option<u64> {
+            let end = (physical_address as usize).checked_add(size)?;
+            if end > self.physical.lock().unwrap().len() {
+                return None;
+            }
+            self.maps.fetch_add(1, Ordering::SeqCst);
+            let mut next = self.next.lock().unwrap();
+            let base = *next;
+            *next += size as u64 + 0x1000;
+            let mut mappings = self.mappings.lock().unwrap();
+            mappings.insert(base, (physical_address, size));
+            self.peak.fetch_max(mappings.len(), Ordering::SeqCst);
+            Some(base)
+        }
+
+        unsafe fn unmap_io_space(&self, virtual_address: u64, size: usize) -> Option<()> {
+            self.unmaps.fetch_add(1, Ordering::SeqCst);
+            let (_, mapped) = self.mappings.lock().unwrap().remove(&virtual_address)?;
+            (mapped == size).then_some(())
+        }
+    }
+
+    impl MemoryRead for MockDriver {
+        fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+            let physical = self.resolve(address, buffer.len())?;
+            buffer.copy_from_slice(&self.physical.lock().unwrap()[physical..physical + buffer.len()]);
+            Some(())
+        }
+    }
+
+    impl MemoryWrite for MockDriver {
+        fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
+            let physical = self.resolve(address, buffer.len())?;
+            self.physical.lock().unwrap()[physical..physical + buffer.len()].copy_from_slice(buffer);
+            Some(())
+        }
+    }
+
+    impl KernelMemoryRead for MockDriver {}
+
+    impl KernelMemoryWrite for MockDriver {}
+
+    fn expected(driver: &MockDriver, address: u64, len: usize) -> Vec<u8> {
+        driver.physical.lock().unwrap()[address as usize..address as usize + len].to_vec()
+    }
+
+    #[test]
+    fn test_reuse() {
+        let mappings = CachedMappings::new(MockDriver::new(0x40000)).window_size(0x4000);
+        let mut buffer = [0u8; 0x10];
+        for i in 0..100 {
+            mappings.try_read_bytes_physical_into(0x1000 + i * 0x10, &mut buffer).unwrap();
+            assert_eq!(buffer.to_vec(), expected(mappings.inner(), 0x1000 + i * 0x10, 0x10));
+        }
+        assert_eq!(mappings.inner().map_count(), 1);
+
+        // a read across two windows maps the second one
+        let mut buffer = [0u8; 0x20];
+        mappings.try_read_bytes_physical_into(0x3FF0, &mut buffer).unwrap();
+        assert_eq!(buffer.to_vec(), expected(mappings.inner(), 0x3FF0, 0x20));
+        assert_eq!(mappings.inner().map_count(), 2);
+
+        mappings.try_write_bytes_physical(0x3FFC, &0x1122334455667788u64.to_le_bytes()).unwrap();
+        assert_eq!(expected(mappings.inner(), 0x3FFC, 8), 0x1122334455667788u64.to_le_bytes());
+        assert_eq!(mappings.inner().map_count(), 2);
+
+        // the blanket impl maps around every access
+        let driver = MockDriver::new(0x40000);
+        for i in 0..10 {
+            driver.try_read_bytes_physical_into(0x1000 + i * 0x10, &mut buffer).unwrap();
+        }
+        assert_eq!((driver.map_count(), driver.unmap_count()), (10, 10));
+
+        // and unmaps even when the access fails
+        driver.fail_access.store(true, Ordering::SeqCst);
+        assert!(driver.try_read_bytes_physical_into(0x1000, &mut buffer).is_none());
+        assert!(driver.try_write_bytes_physical(0x1000, &buffer).is_none());
+        assert_eq!((driver.map_count(), driver.unmap_count()), (12, 12));
+        assert!(driver.mappings.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_eviction_and_drop() {
+        let mappings = CachedMappings::new(MockDriver::new(0x40000)).window_size(0x1000).capacity(3);
+        let mut buffer = [0u8; 4];
+        for page in [0, 1, 2, 0, 3, 0, 1] {
+            mappings.try_read_bytes_physical_into(page * 0x1000, &mut buffer).unwrap();
+        }
+        // page 1 was the least recently used when page 3 was mapped, then page 2 when page 1 was mapped again
+        assert_eq!(mappings.inner().map_count(), 5);
+        assert_eq!(mappings.inner().unmap_count(), 2);
+        assert_eq!(mappings.mapped_windows(), 3);
+        assert_eq!(mappings.inner().peak.load(Ordering::SeqCst), 3);
+
+        mappings.flush();
+        assert_eq!(mappings.inner().unmap_count(), 5);
+        assert!(mappings.inner().mappings.lock().unwrap().is_empty());
+
+        mappings.try_read_bytes_physical_into(0, &mut buffer).unwrap();
+        let mapped = mappings.inner().mappings.clone();
+        assert_eq!(mapped.lock().unwrap().len(), 1);
+        drop(mappings);
+        assert!(mapped.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_end_of_memory() {
+        let mappings = CachedMappings::new(MockDriver::new(0x6000)).window_size(0x4000);
+        let mut buffer = [0u8; 0x10];
+        // the window at 0x4000 would extend past the end of memory, so only the bytes are mapped
+        mappings.try_read_bytes_physical_into(0x5FF0, &mut buffer).unwrap();
+        assert_eq!(buffer.to_vec(), expected(mappings.inner(), 0x5FF0, 0x10));
+        assert_eq!(mappings.mapped_windows(), 0);
+        assert_eq!(mappings.inner().unmap_count(), 1);
+
+        assert!(mappings.try_read_bytes_physical_into(0x5FF8, &mut buffer).is_none());
+        assert!(mappings.try_read_bytes_physical_into(u64::MAX - 4, &mut buffer).is_none());
+    }
+}
@@ -0,0 +1,329 @@
+use std::fs::File;
+use std::path::Path;
+use crate::*;
+
+const LIME_MAGIC: u32 = 0x4C694D45;
+const LIME_HEADER_SIZE: u64 = 32;
+/// The DumpType of a crash dump that contains all of physical memory
+const DUMP_TYPE_FULL: u32 = 1;
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum DumpError {
+    Io(std::io::Error),
+    /// The headers of the dump are invalid or describe more data than the file has
+    InvalidHeader(String),
+    /// The physical memory map in the header could not be read
+    Map(PhysicalMapError),
+}
+
+impl From<std::io::Error> for DumpError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<PhysicalMapError> for DumpError {
+    fn from(e: PhysicalMapError) -> Self {
+        Self::Map(e)
+    }
+}
+
+/// A range of physical memory stored in a dump file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpSegment {
+    pub physical: MemoryRange,
+    /// The offset in the file of the first byte of the range
+    pub file_offset: u64,
+}
+
+/// Physical memory read from a memory image file, so page table walkers and VirtualMemoryReader
+/// can be used on dumps the same way as on a live machine. The file is read with positioned
+/// reads as needed and is never loaded into memory.
+///
+/// Writes go to the file, so they only work if the file was opened for writing
+pub struct DumpFile {
+    file: File,
+    segments: Vec<DumpSegment>,
+}
+
+impl DumpFile {
+    /// Creates a dump from segments that are not overlapping
+    pub fn new(file: File, mut segments: Vec<DumpSegment>) -> Self {
+        segments.retain(|s| s.physical.start < s.physical.end);
+        segments.sort_by_key(|s| s.physical.start);
+        Self { file, segments }
+    }
+
+    /// A raw dump where the offset in the file is the physical address, with holes padded by zeros
+    pub fn raw(file: File) -> Result<Self, DumpError> {
+        let size = file.metadata()?.len();
+        Ok(Self::new(file, vec![DumpSegment { physical: 0..size, file_offset: 0 }]))
+    }
+
+    pub fn open_raw(path: impl AsRef<Path>) -> Result<Self, DumpError> {
+        Self::raw(File::open(path)?)
+    }
+
+    /// A LiME dump, where each range of physical memory is stored after a header with its addresses
+    pub fn lime(file: File) -> Result<Self, DumpError> {
+        let size = file.metadata()?.len();
+        let mut segments = Vec::new();
+        let mut offset = 0;
+
+        while offset < size {
+            let mut header = [0u8; LIME_HEADER_SIZE as usize];
+            read_at(&file, &mut header, offset)?;
+            let u32_at = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+            let u64_at = |at: usize| u64::from_le_bytes(header[at..at + 8].try_into().unwrap());
+
+            if u32_at(0) != LIME_MAGIC {
+                return Err(DumpError::InvalidHeader(format!("no LiME header at {:#X}", offset)));
+            }
+            // the end address is inclusive
+            let (start, end) = (u64_at(8), u64_at(16));
+            let length = end.checked_sub(start).and_then(|l| l.checked_add(1))
+                .ok_or_else(|| DumpError::InvalidHeader(format!("invalid range {:#X}-{:#X} at {:#X}", start, end, offset)))?;
+            let file_offset = offset + LIME_HEADER_SIZE;
+            if file_offset.saturating_add(length) > size {
+                return Err(DumpError::InvalidHeader(format!("the range at {:#X} is truncated", offset)));
+            }
+
+            let physical_end = start.checked_add(length)
+                .ok_or_else(|| DumpError::InvalidHeader(format!("the range at {:#X} ends past the address space", offset)))?;
+            segments.push(DumpSegment { physical: start..physical_end, file_offset });
+            offset = file_offset + length;
+        }
+        Ok(Self::new(file, segments))
+    }
+
+    pub fn open_lime(path: impl AsRef<Path>) -> Result<Self, DumpError> {
+        Self::lime(File::open(path)?)
+    }
+
+    /// A sparse dump that stores the RAM ranges of the map back to back, starting at data_offset
+    pub fn sparse(file: File, map: &PhysicalMemoryMap, data_offset: u64) -> Result<Self, DumpError> {
+        let size = file.metadata()?.len();
+        let mut segments = Vec::new();
+        let mut file_offset = data_offset;
+        for range in map.ranges() {
+            segments.push(DumpSegment { physical: range.clone(), file_offset });
+            file_offset = file_offset.checked_add(range.end - range.start)
+                .ok_or_else(|| DumpError::InvalidHeader("the ranges are larger than any file".to_string()))?;
+        }
+        if file_offset > size {
+            return Err(DumpError::InvalidHeader(format!("the ranges need {:#X} bytes but the file is {:#X} bytes", file_offset, size)));
+        }
+        Ok(Self::new(file, segments))
+    }
+
+    /// A full Windows crash dump, which is a sparse dump whose range table is in the header
+    pub fn crash_dump(file: File) -> Result<Self, DumpError> {
+        let mut header = vec![0u8; 0x1000];
+        read_at(&file, &mut header, 0)?;
+        let map = PhysicalMemoryMap::from_dump_header(&header)?;
+        let (data_offset, dump_type_offset) = if header.starts_with(b"PAGEDU64") { (0x2000, 0xF98) } else { (0x1000, 0xF88) };
+
+        // kernel and bitmap dumps only store some pages of the runs, so their data is not sparse
+        let dump_type = u32::from_le_bytes(header[dump_type_offset..dump_type_offset + 4].try_into().unwrap());
+        if dump_type != DUMP_TYPE_FULL {
+            return Err(DumpError::InvalidHeader(format!("the dump type {} is not a full dump", dump_type)));
+        }
+        Self::sparse(file, &map, data_offset)
+    }
+
+    pub fn open_crash_dump(path: impl AsRef<Path>) -> Result<Self, DumpError> {
+        Self::crash_dump(File::open(path)?)
+    }
+
+    /// Returns the ranges of physical memory in the dump, sorted by physical address
+    pub fn segments(&self) -> &[DumpSegment] {
+        &self.segments
+    }
+
+    /// Returns the physical memory in the dump as a PhysicalMemoryMap
+    pub fn memory_map(&self) -> PhysicalMemoryMap {
+        PhysicalMemoryMap::new(self.segments.iter().map(|s| s.physical.clone()))
+    }
+
+    /// Returns the file offset of every piece of the len bytes at physical_address with its range
+    /// in the buffer, or None if any byte is not in the dump
+    fn file_chunks(&self, physical_address: u64, len: usize) -> Option<Vec<(u64, core::ops::Range<usize>)>> {
+        let mut chunks = Vec::new();
+        let mut offset = 0;
+        while offset < len {
+            let address = physical_address.checked_add(offset as u64)?;
+            let index = self.segments.partition_point(|s| s.physical.end <= address);
+            let segment = self.segments.get(index).filter(|s| s.physical.start <= address)?;
+            let size = ((segment.physical.end - address) as usize).min(len - offset);
+            chunks.push((segment.file_offset + (address - segment.physical.start), offset..offset + size));
+            offset += size;
+        }
+        Some(chunks)
+    }
+}
+
+impl PhysicalMemoryRead for DumpFile {
+    fn try_read_bytes_physical_into(&self, physical_address: u64, buffer: &mut [u8]) -> Option<()> {
+        for (file_offset, range) in self.file_chunks(physical_address, buffer.len())? {
+            read_at(&self.file, &mut buffer[range], file_offset).ok()?;
+        }
+        Some(())
+    }
+}
+
+impl PhysicalMemoryWrite for DumpFile {
+    fn try_write_bytes_physical(&self, physical_address: u64, buffer: &[u8]) -> Option<()> {
+        for (file_offset, range) in self.file_chunks(physical_address, buffer.len())? {
+            write_at(&self.file, &buffer[range], file_offset).ok()?;
+        }
+        Some(())
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buffer, offset)
+}
+
+#[cfg(unix)]
+fn write_at(file: &File, buffer: &[u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buffer, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, mut buffer: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    while !buffer.is_empty() {
+        match std::os::windows::fs::FileExt::seek_read(file, buffer, offset)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            read => {
+                buffer = &mut std::mem::take(&mut buffer)[read..];
+                offset += read as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn write_at(file: &File, mut buffer: &[u8], mut offset: u64) -> std::io::Result<()> {
+    while !buffer.is_empty() {
+        match std::os::windows::fs::FileExt::seek_write(file, buffer, offset)? {
+            0 => return Err(std::io::ErrorKind::WriteZero.into()),
+            written => {
+                buffer = &buffer[written..];
+                offset += written as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::page_table::tests::PageTableBuilder;
+    use std::path::PathBuf;
+
+    /// A file in the temp directory that is removed when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!("memlib-dump-{}-{}", std::process::id(), name));
+            std::fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn lime_range(start: u64, data: &[u8]) -> Vec<u8> {
+        let mut range = Vec::new();
+        range.extend_from_slice(&LIME_MAGIC.to_le_bytes());
+        range.extend_from_slice(&1u32.to_le_bytes());
+        range.extend_from_slice(&start.to_le_bytes());
+        range.extend_from_slice(&(start + (data.len() as u64 - 1)).to_le_bytes());
+        range.extend_from_slice(&[0; 8]);
+        range.extend_from_slice(data);
+        range
+    }
+
+    #[test]
+    fn test_raw_offline_walk() {
+        let mut builder = PageTableBuilder::new(0x20000, PagingMode::FourLevel);
+        builder.map_page(0x7FF6_0000_1000, 0x10000);
+        builder.map_page(0x7FF6_0000_2000, 0x12000);
+        builder.memory.write(0x10FFC, &0x11223344u32);
+        builder.memory.write(0x12000, &0x55667788u32);
+        let file = TempFile::new("raw", &builder.memory.try_read_bytes(0, 0x20000).unwrap());
+
+        let walker = PageTableWalker::new(DumpFile::open_raw(&file.0).unwrap(), builder.dtb);
+        assert_eq!(walker.virtual_reader().read::<u64>(0x7FF6_0000_1FFC), 0x55667788_11223344);
+        assert!(walker.physical_reader().try_read_bytes(0x1FFFC, 8).is_none());
+
+        let writable = DumpFile::raw(std::fs::OpenOptions::new().read(true).write(true).open(&file.0).unwrap()).unwrap();
+        assert!(writable.try_write_bytes_physical(0x10000, &[1, 2, 3, 4]).is_some());
+        assert!(walker.inner().try_write_bytes_physical(0x10000, &[1, 2, 3, 4]).is_none(), "the file was opened read only");
+        assert_eq!(walker.physical_reader().try_read_bytes(0x10000, 4).unwrap(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_lime() {
+        let low: Vec<u8> = (0..0x1000).map(|i| i as u8).collect();
+        let high: Vec<u8> = (0..0x2000).map(|i| (i >> 4) as u8).collect();
+        let mut contents = lime_range(0x1000, &low);
+        contents.extend(lime_range(0x10_0000, &high));
+        let file = TempFile::new("lime", &contents);
+
+        let dump = DumpFile::open_lime(&file.0).unwrap();
+        assert_eq!(dump.memory_map().ranges(), [0x1000..0x2000, 0x10_0000..0x10_2000]);
+        assert_eq!(dump.physical_reader().try_read_bytes(0x1FF0, 0x10).unwrap(), low[0xFF0..]);
+        assert_eq!(dump.physical_reader().try_read_bytes(0x10_1000, 0x20).unwrap(), high[0x1000..0x1020]);
+        assert!(dump.physical_reader().try_read_bytes(0x1FF0, 0x20).is_none());
+        assert!(dump.physical_reader().try_read_bytes(0, 4).is_none());
+
+        contents.truncate(contents.len() - 1);
+        let truncated = TempFile::new("lime-truncated", &contents);
+        assert!(matches!(DumpFile::open_lime(&truncated.0), Err(DumpError::InvalidHeader(_))));
+
+        let overflowing = TempFile::new("lime-overflow", &lime_range(u64::MAX, &[0]));
+        assert!(matches!(DumpFile::open_lime(&overflowing.0), Err(DumpError::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn test_crash_dump() {
+        let mut contents = b"PAGE".repeat(0x800);
+        contents[4..8].copy_from_slice(b"DU64");
+        contents[0x88..0x8C].copy_from_slice(&2u32.to_le_bytes());
+        contents[0xF98..0xF9C].copy_from_slice(&DUMP_TYPE_FULL.to_le_bytes());
+        for (i, value) in [1u64, 1, 0x10, 2].iter().enumerate() {
+            contents[0x98 + i * 8..0xA0 + i * 8].copy_from_slice(&value.to_le_bytes());
+        }
+        contents.extend((0..0x3000).map(|i| (i / 0x1000) as u8 + 1));
+        let file = TempFile::new("crash", &contents);
+
+        let dump = DumpFile::open_crash_dump(&file.0).unwrap();
+        assert_eq!(dump.segments()[1], DumpSegment { physical: 0x10000..0x12000, file_offset: 0x3000 });
+        assert_eq!(dump.physical_reader().try_read_bytes(0x1FFF, 1).unwrap(), [1]);
+        assert_eq!(dump.physical_reader().try_read_bytes(0x10FFE, 4).unwrap(), [2, 2, 3, 3]);
+        assert!(dump.physical_reader().try_read_bytes(0x2000, 1).is_none());
+
+        // the header needs more data than the file has
+        let short = TempFile::new("crash-short", &contents[..0x4000]);
+        assert!(DumpFile::open_crash_dump(&short.0).is_err());
+
+        // a kernel dump stores fewer pages than its runs describe
+        contents[0xF98..0xF9C].copy_from_slice(&2u32.to_le_bytes());
+        let kernel = TempFile::new("crash-kernel", &contents);
+        assert!(matches!(DumpFile::open_crash_dump(&kernel.0), Err(DumpError::InvalidHeader(_))));
+
+        contents[0x88..0x8C].copy_from_slice(b"PAGE");
+        let no_runs = TempFile::new("crash-no-runs", &contents);
+        assert!(matches!(DumpFile::open_crash_dump(&no_runs.0), Err(DumpError::Map(PhysicalMapError::InvalidHeader(_)))));
+    }
+}
@@ -0,0 +1,371 @@
+use crate::*;
+
+/// The bits of a page table entry that hold the physical address of the next table or page
+pub const PHYSICAL_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+const PRESENT: u64 = 1 << 0;
+const LARGE_PAGE: u64 = 1 << 7;
+/// Windows software PTE bits for pages that are not present but still in physical memory
+const PROTOTYPE: u64 = 1 << 10;
+const TRANSITION: u64 = 1 << 11;
+/// The PAT bit of a large page entry, which is inside the address bits
+const LARGE_PAGE_PAT: u64 = 1 << 12;
+
+const LEVELS: [PageTableLevel; 5] = [
+    PageTableLevel::Pml5,
+    PageTableLevel::Pml4,
+    PageTableLevel::Pdpt,
+    PageTableLevel::Pd,
+    PageTableLevel::Pt,
+];
+
+/// The number of page table levels used for translation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagingMode {
+    /// PML4, PDPT, PD and PT
+    FourLevel,
+    /// PML5, PML4, PDPT, PD and PT
+    FiveLevel,
+}
+
+impl PagingMode {
+    /// Returns the number of page table levels walked for an address
+    pub fn levels(self) -> u32 {
+        match self {
+            Self::FourLevel => 4,
+            Self::FiveLevel => 5,
+        }
+    }
+
+    /// Returns true if the bits above the highest translated bit of the address are copies
+    /// of that bit, bit 47 with four levels and bit 56 with five levels
+    pub fn is_canonical(self, virtual_address: u64) -> bool {
+        let unused_bits = 64 - (12 + 9 * self.levels());
+        ((virtual_address << unused_bits) as i64 >> unused_bits) as u64 == virtual_address
+    }
+}
+
+/// A translator that walks the page tables of one address space
+pub trait PageTableWalk: TranslatePhysical {
+    /// Returns the directory table base (CR3) of the address space that is translated
+    fn directory_table_base(&self) -> u64;
+
+    /// Continues translating virtual_address from the table referenced by the last of entries,
+    /// which are the non-leaf entries read for the address so far. With no entries this is
+    /// the same as translate
+    fn translate_from(&self, virtual_address: u64, entries: Vec<PageTableEntry>) -> Result<Translation, TranslationError>;
+}
+
+/// Translates x86-64 virtual addresses by walking the page tables of an address space in physical memory.
+/// Handles 1GiB and 2MiB large pages and, optionally, Windows transition PTEs
+pub struct PageTableWalker<P: PhysicalMemoryRead> {
+    physical: P,
+    dtb: u64,
+    mode: PagingMode,
+    address_mask: u64,
+    transition: bool,
+}
+
+impl<P: PhysicalMemoryRead> PageTableWalker<P> {
+    /// Creates a four level walker for the address space with the directory table base (CR3) dtb
+    pub fn new(physical: P, dtb: u64) -> Self {
+        Self { physical, dtb, mode: PagingMode::FourLevel, address_mask: PHYSICAL_ADDRESS_MASK, transition: false }
+    }
+
+    pub fn paging_mode(mut self, mode: PagingMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the mask applied to entries to get physical addresses. Defaults to PHYSICAL_ADDRESS_MASK,
+    /// but can be narrowed to the MAXPHYADDR of the processor
+    pub fn address_mask(mut self, mask: u64) -> Self {
+        self.address_mask = mask;
+        self
+    }
+
+    /// Treats PTEs that are not present but have the transition bit set as valid, like Windows
+    /// does for pages on the standby and modified lists
+    pub fn allow_transition(mut self, allow: bool) -> Self {
+        self.transition = allow;
+        self
+    }
+
+    /// Returns the directory table base of the address space
+    pub fn dtb(&self) -> u64 {
+        self.dtb
+    }
+
+    /// Changes the address space that is walked
+    pub fn set_dtb(&mut self, dtb: u64) {
+        self.dtb = dtb;
+    }
+
+    /// Returns a reference to the physical memory
+    pub fn inner(&self) -> &P {
+        &self.physical
+    }
+
+    /// Consumes the walker and returns the physical memory
+    pub fn into_inner(self) -> P {
+        self.physical
+    }
+
+    fn read_entry(&self, address: u64) -> Option<u64> {
+        let mut entry = [0u8; 8];
+        self.physical.try_read_bytes_physical_into(address, &mut entry)?;
+        Some(u64::from_le_bytes(entry))
+    }
+}
+
+impl<P: PhysicalMemoryRead> TranslatePhysical for PageTableWalker<P> {
+    fn physical_address(&self, virtual_address: u64) -> Option<u64> {
+        self.translate(virtual_address).ok().map(|t| t.physical_address)
+    }
+
+    fn translate(&self, virtual_address: u64) -> Result<Translation, TranslationError> {
+        self.translate_from(virtual_address, Vec::with_capacity(self.mode.levels() as usize))
+    }
+}
+
+impl<P: PhysicalMemoryRead> PageTableWalk for PageTableWalker<P> {
+    fn directory_table_base(&self) -> u64 {
+        self.dtb
+    }
+
+    fn translate_from(&self, virtual_address: u64, mut entries: Vec<PageTableEntry>) -> Result<Translation, TranslationError> {
+        if !self.mode.is_canonical(virtual_address) {
+            return Err(TranslationError::NonCanonical { address: virtual_address });
+        }
+        let levels: &[PageTableLevel] = match self.mode {
+            PagingMode::FourLevel => &LEVELS[1..],
+            PagingMode::FiveLevel => &LEVELS,
+        };
+        let mut table = entries.last().map_or(self.dtb, |e| e.value) & self.address_mask;
+
+        for &level in &levels[entries.len().min(levels.len())..] {
+            let address = table + ((virtual_address >> level.shift()) & 0x1FF) * 8;
+            let entry = self.read_entry(address).ok_or(TranslationError::ReadFailed { level, address })?;
+            entries.push(PageTableEntry { level, address, value: entry });
+
+            if entry & PRESENT == 0 {
+                let transition = level == PageTableLevel::Pt && entry & (TRANSITION | PROTOTYPE) == TRANSITION;
+                if !(transition && self.transition) {
+                    return Err(match (level, entry) {
+                        (PageTableLevel::Pt, entry) if entry != 0 => TranslationError::PagedOut { entry },
+                        _ => TranslationError::NotPresent { level, entry },
+                    });
+                }
+            }
+
+            let page_size = level.entry_size();
+            let is_leaf = match level {
+                PageTableLevel::Pt => true,
+                PageTableLevel::Pdpt | PageTableLevel::Pd => entry & LARGE_PAGE != 0,
+                PageTableLevel::Pml5 | PageTableLevel::Pml4 if entry & LARGE_PAGE != 0 => {
+                    return Err(TranslationError::InvalidEntry { level, entry });
+                }
+                _ => false,
+            };
+            if !is_leaf {
+                table = entry & self.address_mask;
+                continue;
+            }
+
+            // the low bits of a large page address are reserved, apart from the PAT bit
+            let base = entry & self.address_mask;
+            if level != PageTableLevel::Pt && base & (page_size - 1) & !LARGE_PAGE_PAT != 0 {
+                return Err(TranslationError::InvalidEntry { level, entry });
+            }
+            let flags = PageFlags::effective(&entries);
+            return Ok(Translation {
+                physical_address: (base & !(page_size - 1)) + (virtual_address & (page_size - 1)),
+                page_size,
+                flags: Some(flags),
+                entries,
+            });
+        }
+
+        // only reached when entries already ended in a leaf
+        Err(TranslationError::Unmapped)
+    }
+}
+
+impl<P: PhysicalMemoryRead> PhysicalMemoryRead for PageTableWalker<P> {
+    fn try_read_bytes_physical_into(&self, physical_address: u64, buffer: &mut [u8]) -> Option<()> {
+        self.physical.try_read_bytes_physical_into(physical_address, buffer)
+    }
+}
+
+impl<P: PhysicalMemoryRead + PhysicalMemoryWrite> PhysicalMemoryWrite for PageTableWalker<P> {
+    fn try_write_bytes_physical(&self, physical_address: u64, buffer: &[u8]) -> Option<()> {
+        self.physical.try_write_bytes_physical(physical_address, buffer)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Builds page tables in a buffer of physical memory, allocating tables from 0x1000 upwards
+    pub(crate) struct PageTableBuilder {
+        pub memory: MemoryBuffer<Vec<u8>>,
+        pub dtb: u64,
+        levels: u32,
+        next_table: u64,
+    }
+
+    impl PageTableBuilder {
+        pub fn new(size: usize, mode: PagingMode) -> Self {
+            Self { memory: MemoryBuffer::new(0, vec![0u8; size]), dtb: 0x1000, levels: mode.levels(), next_table: 0x2000 }
+        }
+
+        fn table(&mut self, table: u64, index: u64) -> u64 {
+            let entry = self.memory.read::<u64>(table + index * 8);
+            if entry & PRESENT != 0 {
+                return entry & PHYSICAL_ADDRESS_MASK;
+            }
+            let next = self.next_table;
+            self.next_table += 0x1000;
+            self.memory.write(table + index * 8, &(next | PRESENT | 0x2));
+            next
+        }
+
+        /// Maps the page containing virtual_address with an entry at the level that maps
+        /// page_size pages. Returns the physical address of the entry
+        pub fn map(&mut self, virtual_address: u64, entry: u64, page_size: u64) -> u64 {
+            let target_level = match page_size {
+                0x1000 => 1,
+                0x20_0000 => 2,
+                0x4000_0000 => 3,
+                _ => panic!("invalid page size {:#X}", page_size),
+            };
+            let mut table = self.dtb;
+            for level in (target_level + 1..=self.levels).rev() {
+                table = self.table(table, (virtual_address >> (12 + 9 * (level - 1))) & 0x1FF);
+            }
+            let entry_address = table + ((virtual_address >> (12 + 9 * (target_level - 1))) & 0x1FF) * 8;
+            let entry = if target_level == 1 { entry } else { entry | LARGE_PAGE };
+            self.memory.write(entry_address, &entry);
+            entry_address
+        }
+
+        pub fn map_page(&mut self, virtual_address: u64, physical_address: u64) -> u64 {
+            self.map(virtual_address, physical_address | PRESENT | 0x2, 0x1000)
+        }
+    }
+
+    #[test]
+    fn test_four_level() {
+        let mut builder = PageTableBuilder::new(0x20000, PagingMode::FourLevel);
+        builder.map_page(0x7FF6_1234_5000, 0x10000);
+        builder.map_page(0xFFFF_F800_0000_0000, 0x11000);
+        builder.map(0x4000_0000, 0x4000_0000 | PRESENT, 0x4000_0000);
+        builder.map(0x60_0000, 0x20_0000 | PRESENT, 0x20_0000);
+        builder.memory.write(0x10ABC, &0xDEADBEEFu32);
+
+        let walker = PageTableWalker::new(builder.memory, builder.dtb | 0x5);
+        assert_eq!(walker.physical_address(0x7FF6_1234_5ABC), Some(0x10ABC));
+        assert_eq!(walker.physical_address(0xFFFF_F800_0000_0010), Some(0x11010));
+        assert_eq!(walker.physical_address(0x4123_4567), Some(0x4123_4567));
+        assert_eq!(walker.physical_address(0x7F_FFFF), Some(0x3F_FFFF));
+        assert_eq!(walker.physical_address(0x7FF6_1234_6000), None);
+        assert_eq!(walker.physical_address(0x1000), None);
+
+        assert_eq!(walker.virtual_reader().read::<u32>(0x7FF6_1234_5ABC), 0xDEADBEEF);
+    }
+
+    #[test]
+    fn test_five_level() {
+        let mut builder = PageTableBuilder::new(0x20000, PagingMode::FiveLevel);
+        builder.map_page(0x00FF_0000_0000_3000, 0x10000);
+
+        let walker = PageTableWalker::new(builder.memory, builder.dtb).paging_mode(PagingMode::FiveLevel);
+        assert_eq!(walker.physical_address(0x00FF_0000_0000_3008), Some(0x10008));
+        assert_eq!(walker.physical_address(0x0000_0000_0000_3008), None);
+    }
+
+    #[test]
+    fn test_non_canonical() {
+        assert!(PagingMode::FourLevel.is_canonical(0x7FFF_FFFF_FFFF));
+        assert!(PagingMode::FourLevel.is_canonical(0xFFFF_8000_0000_0000));
+        assert!(!PagingMode::FourLevel.is_canonical(0x8000_0000_0000));
+        assert!(PagingMode::FiveLevel.is_canonical(0x8000_0000_0000));
+        assert!(!PagingMode::FiveLevel.is_canonical(0x0100_0000_0000_0000));
+
+        let mut builder = PageTableBuilder::new(0x20000, PagingMode::FourLevel);
+        builder.map_page(0xFFFF_F800_0000_0000, 0x10000);
+
+        // the walk would select the same entries, but the bits above bit 47 do not match it
+        let walker = PageTableWalker::new(builder.memory, builder.dtb);
+        assert_eq!(walker.physical_address(0xFFFF_F800_0000_0000), Some(0x10000));
+        assert_eq!(
+            walker.translate(0x0000_F800_0000_0000),
+            Err(TranslationError::NonCanonical { address: 0x0000_F800_0000_0000 })
+        );
+        assert_eq!(
+            walker.translate(0x7FFF_F800_0000_0000),
+            Err(TranslationError::NonCanonical { address: 0x7FFF_F800_0000_0000 })
+        );
+    }
+
+    #[test]
+    fn test_transition_and_mask() {
+        let mut builder = PageTableBuilder::new(0x20000, PagingMode::FourLevel);
+        builder.map(0x1000, 0x10000 | TRANSITION, 0x1000);
+        builder.map(0x2000, 0x11000 | TRANSITION | PROTOTYPE, 0x1000);
+        // the NX bit and software bits above the address must not leak into the address
+        builder.map(0x3000, 0x12000 | PRESENT | (1 << 63) | (0x7F << 52), 0x1000);
+
+        let walker = PageTableWalker::new(builder.memory, builder.dtb);
+        assert_eq!(walker.physical_address(0x1000), None);
+        assert_eq!(walker.physical_address(0x3010), Some(0x12010));
+
+        let walker = walker.allow_transition(true);
+        assert_eq!(walker.physical_address(0x1010), Some(0x10010));
+        assert_eq!(walker.physical_address(0x2000), None);
+    }
+
+    #[test]
+    fn test_translation_details() {
+        const WRITABLE: u64 = 0x2;
+        const USER: u64 = 0x4;
+        const NO_EXECUTE: u64 = 1 << 63;
+
+        let mut builder = PageTableBuilder::new(0x20000, PagingMode::FourLevel);
+        builder.map(0x1000, 0x10000 | PRESENT | WRITABLE | USER | NO_EXECUTE, 0x1000);
+        // intermediate tables are writable but not user accessible
+        builder.map(0x60_0000, 0x20_0000 | PRESENT | USER, 0x20_0000);
+        builder.map(0x3000, 0x12000 | TRANSITION, 0x1000);
+        builder.map(0x4000, 0, 0x1000);
+        // bit 12 is the PAT bit, but bit 13 is reserved in a 1GiB page entry
+        builder.map(0x4000_0000, 0x4000_2000 | PRESENT, 0x4000_0000);
+        let pml4e = builder.dtb + 0x1FF * 8;
+        builder.memory.write(pml4e, &(0x3000u64 | PRESENT | LARGE_PAGE));
+
+        let walker = PageTableWalker::new(builder.memory, builder.dtb);
+
+        let translation = walker.translate(0x1234).unwrap();
+        assert_eq!((translation.physical_address, translation.page_size), (0x10234, 0x1000));
+        assert_eq!(translation.entries.len(), 4);
+        assert_eq!(translation.entries[3].level, PageTableLevel::Pt);
+        let flags = translation.flags.unwrap();
+        assert!(flags.contains(PageFlags::PRESENT | PageFlags::WRITABLE | PageFlags::NO_EXECUTE));
+        assert!(!flags.contains(PageFlags::USER));
+
+        let translation = walker.translate(0x65_4321).unwrap();
+        assert_eq!((translation.physical_address, translation.page_size), (0x25_4321, 0x20_0000));
+        assert_eq!(translation.entries.len(), 3);
+        assert!(!translation.flags.unwrap().contains(PageFlags::WRITABLE));
+
+        assert_eq!(walker.translate(0x3000), Err(TranslationError::PagedOut { entry: 0x12000 | TRANSITION }));
+        assert_eq!(walker.translate(0x4000), Err(TranslationError::NotPresent { level: PageTableLevel::Pt, entry: 0 }));
+        assert_eq!(walker.translate(0x8000_0000), Err(TranslationError::NotPresent { level: PageTableLevel::Pdpt, entry: 0 }));
+        assert!(matches!(walker.translate(0x4000_0000), Err(TranslationError::InvalidEntry { level: PageTableLevel::Pdpt, .. })));
+        assert!(matches!(walker.translate(0xFFFF_FF80_0000_0000), Err(TranslationError::InvalidEntry { level: PageTableLevel::Pml4, .. })));
+        assert!(matches!(
+            PageTableWalker::new(MemoryBuffer::new(0, vec![0u8; 0x100]), 0x1000).translate(0),
+            Err(TranslationError::ReadFailed { level: PageTableLevel::Pml4, address: 0x1000 })
+        ));
+    }
+}
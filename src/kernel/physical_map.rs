@@ -0,0 +1,417 @@
+use std::path::Path;
+use crate::*;
+
+/// The physical address ranges of a machine that are RAM. Everything else is a hole, device
+/// memory or firmware reserved memory, and reading it can hang or crash the machine
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PhysicalMemoryMap {
+    ranges: Vec<MemoryRange>,
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum PhysicalMapError {
+    Io(std::io::Error),
+    InvalidLine { line: usize, text: String },
+    InvalidHeader(String),
+    /// Every address in /proc/iomem is zero, which happens when it is read without root
+    AddressesHidden,
+}
+
+impl From<std::io::Error> for PhysicalMapError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// The type of a range in a firmware memory map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKind {
+    Ram,
+    Reserved,
+    AcpiReclaimable,
+    AcpiNvs,
+    Unusable,
+    Mmio,
+    Persistent,
+    /// A type that is not known, with its raw value
+    Other(u32),
+}
+
+impl MemoryKind {
+    /// Converts a BIOS E820 range type
+    pub fn from_e820(kind: u32) -> Self {
+        match kind {
+            1 => Self::Ram,
+            2 => Self::Reserved,
+            3 => Self::AcpiReclaimable,
+            4 => Self::AcpiNvs,
+            5 => Self::Unusable,
+            7 => Self::Persistent,
+            kind => Self::Other(kind),
+        }
+    }
+
+    /// Converts an EFI_MEMORY_TYPE. Loader and boot services memory is RAM once the
+    /// operating system has started
+    pub fn from_efi(kind: u32) -> Self {
+        match kind {
+            1..=4 | 7 => Self::Ram,
+            0 | 5 | 6 => Self::Reserved,
+            8 => Self::Unusable,
+            9 => Self::AcpiReclaimable,
+            10 => Self::AcpiNvs,
+            11 | 12 => Self::Mmio,
+            14 => Self::Persistent,
+            kind => Self::Other(kind),
+        }
+    }
+}
+
+/// An entry of a firmware memory map
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryMapEntry {
+    pub range: MemoryRange,
+    pub kind: MemoryKind,
+}
+
+impl MemoryMapEntry {
+    /// Creates an entry from the fields of an E820 entry
+    pub fn e820(base: u64, length: u64, kind: u32) -> Self {
+        Self { range: base..base.saturating_add(length), kind: MemoryKind::from_e820(kind) }
+    }
+
+    /// Creates an entry from the fields of an EFI_MEMORY_DESCRIPTOR, which counts 4KiB pages
+    pub fn efi(physical_start: u64, number_of_pages: u64, kind: u32) -> Self {
+        let end = physical_start.saturating_add(number_of_pages.saturating_mul(0x1000));
+        Self { range: physical_start..end, kind: MemoryKind::from_efi(kind) }
+    }
+}
+
+impl PhysicalMemoryMap {
+    /// Creates a map from RAM ranges. Overlapping and adjacent ranges are merged and empty ranges are dropped
+    pub fn new(ranges: impl IntoIterator<Item = MemoryRange>) -> Self {
+        let mut sorted: Vec<MemoryRange> = ranges.into_iter().filter(|r| r.start < r.end).collect();
+        sorted.sort_by_key(|r| r.start);
+
+        let mut merged: Vec<MemoryRange> = Vec::with_capacity(sorted.len());
+        for range in sorted {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        Self { ranges: merged }
+    }
+
+    /// Creates a map from the RAM entries of a firmware memory map
+    pub fn from_entries(entries: impl IntoIterator<Item = MemoryMapEntry>) -> Self {
+        Self::new(entries.into_iter().filter(|e| e.kind == MemoryKind::Ram).map(|e| e.range))
+    }
+
+    /// Reads /proc/iomem of this machine. Needs root, since the addresses are hidden otherwise
+    pub fn from_proc_iomem() -> Result<Self, PhysicalMapError> {
+        Self::from_iomem_file("/proc/iomem")
+    }
+
+    pub fn from_iomem_file(path: impl AsRef<Path>) -> Result<Self, PhysicalMapError> {
+        Self::from_iomem(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parses text in the format of /proc/iomem. Only top level "System RAM" entries are RAM,
+    /// indented entries are parts of the entry above them
+    pub fn from_iomem(text: &str) -> Result<Self, PhysicalMapError> {
+        let mut ranges = Vec::new();
+        let mut hidden = true;
+
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            // 00100000-bffdffff : System RAM
+            let invalid = || PhysicalMapError::InvalidLine { line: i + 1, text: line.to_string() };
+            let (range, name) = line.trim().split_once(" : ").ok_or_else(invalid)?;
+            let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+            let start = u64::from_str_radix(start, 16).map_err(|_| invalid())?;
+            let end = u64::from_str_radix(end, 16).map_err(|_| invalid())?;
+
+            hidden &= start == 0 && end == 0;
+            if !line.starts_with(char::is_whitespace) && name.trim() == "System RAM" {
+                ranges.push(start..end.saturating_add(1));
+            }
+        }
+
+        if hidden && !ranges.is_empty() {
+            return Err(PhysicalMapError::AddressesHidden);
+        }
+        Ok(Self::new(ranges))
+    }
+
+    /// Reads the physical memory runs from the header of a Windows crash dump, for both
+    /// 32 bit (PAGEDUMP) and 64 bit (PAGEDU64) dumps
+    pub fn from_dump_header(header: &[u8]) -> Result<Self, PhysicalMapError> {
+        let u32_at = |offset: usize| header.get(offset..offset + 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()) as u64);
+        let u64_at = |offset: usize| header.get(offset..offset + 8).map(|b| u64::from_le_bytes(b.try_into().unwrap()));
+        let truncated = || PhysicalMapError::InvalidHeader("the header is truncated".to_string());
+
+        // the offset of the run count, the offset of the first run and whether run fields are 64 bit
+        let (block, runs, is_64) = match header.get(..8) {
+            Some(b"PAGEDU64") => (0x88, 0x98, true),
+            Some(b"PAGEDUMP") => (0x64, 0x6C, false),
+            _ => return Err(PhysicalMapError::InvalidHeader("the signature is not PAGEDUMP or PAGEDU64".to_string())),
+        };
+
+        let count = u32_at(block).ok_or_else(truncated)?;
+        // uninitialized fields of the header are filled with the signature
+        if count == u32::from_le_bytes(*b"PAGE") as u64 {
+            return Err(PhysicalMapError::InvalidHeader("the dump has no physical memory runs".to_string()));
+        }
+
+        let mut ranges = Vec::new();
+        for i in 0..count as usize {
+            let (base_page, page_count) = if is_64 {
+                (u64_at(runs + i * 16), u64_at(runs + i * 16 + 8))
+            } else {
+                (u32_at(runs + i * 8), u32_at(runs + i * 8 + 4))
+            };
+            let (base_page, page_count) = base_page.zip(page_count).ok_or_else(truncated)?;
+            ranges.push(base_page.saturating_mul(0x1000)..base_page.saturating_add(page_count).saturating_mul(0x1000));
+        }
+        Ok(Self::new(ranges))
+    }
+
+    /// Returns the sorted, non overlapping RAM ranges
+    pub fn ranges(&self) -> &[MemoryRange] {
+        &self.ranges
+    }
+
+    /// Returns the number of bytes of RAM
+    pub fn total_size(&self) -> u64 {
+        self.ranges.iter().map(|r| r.end - r.start).sum()
+    }
+
+    /// Returns true if every byte of the len bytes at address is RAM
+    pub fn contains(&self, address: u64, len: usize) -> bool {
+        let Some(end) = address.checked_add(len as u64) else { return false };
+        self.range_containing(address).map_or(len == 0, |r| end <= r.end)
+    }
+
+    /// Returns the RAM range containing address
+    pub fn range_containing(&self, address: u64) -> Option<&MemoryRange> {
+        let index = self.ranges.partition_point(|r| r.end <= address);
+        self.ranges.get(index).filter(|r| r.start <= address)
+    }
+
+    /// Returns the physical address of every piece of the len bytes at address that is RAM,
+    /// with its range in a buffer of len bytes
+    pub fn ram_chunks(&self, address: u64, len: usize) -> Vec<(u64, core::ops::Range<usize>)> {
+        let end = address.saturating_add(len as u64);
+        let first = self.ranges.partition_point(|r| r.end <= address);
+        self.ranges[first..].iter()
+            .take_while(|r| r.start < end)
+            .map(|r| {
+                let start = r.start.max(address);
+                let offset = (start - address) as usize;
+                (start, offset..offset + (r.end.min(end) - start) as usize)
+            })
+            .collect()
+    }
+}
+
+/// Wraps physical memory and only reads and writes addresses that are RAM in a PhysicalMemoryMap,
+/// so stray reads from bad translations never touch device memory.
+///
+/// Reads and writes fail unless every byte is RAM. Reads that are partly RAM can read the RAM
+/// pieces and fill the holes with zeros instead, if zero_fill_holes is enabled
+pub struct GuardedPhysical<P> {
+    inner: P,
+    map: PhysicalMemoryMap,
+    zero_fill_holes: bool,
+}
+
+impl<P> GuardedPhysical<P> {
+    pub fn new(inner: P, map: PhysicalMemoryMap) -> Self {
+        Self { inner, map, zero_fill_holes: false }
+    }
+
+    /// Lets reads that are partly RAM succeed, with the bytes that are not RAM set to zero.
+    /// Reads without any RAM still fail
+    pub fn zero_fill_holes(mut self, zero_fill_holes: bool) -> Self {
+        self.zero_fill_holes = zero_fill_holes;
+        self
+    }
+
+    pub fn map(&self) -> &PhysicalMemoryMap {
+        &self.map
+    }
+
+    /// Returns a reference to the inner physical memory
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Consumes the wrapper and returns the inner physical memory
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+}
+
+impl<P: PhysicalMemoryRead> PhysicalMemoryRead for GuardedPhysical<P> {
+    fn try_read_bytes_physical_into(&self, physical_address: u64, buffer: &mut [u8]) -> Option<()> {
+        if self.map.contains(physical_address, buffer.len()) {
+            return self.inner.try_read_bytes_physical_into(physical_address, buffer);
+        }
+        if !self.zero_fill_holes {
+            return None;
+        }
+
+        let chunks = self.map.ram_chunks(physical_address, buffer.len());
+        if chunks.is_empty() {
+            return None;
+        }
+        buffer.fill(0);
+        for (address, range) in chunks {
+            self.inner.try_read_bytes_physical_into(address, &mut buffer[range])?;
+        }
+        Some(())
+    }
+}
+
+impl<P: PhysicalMemoryWrite> PhysicalMemoryWrite for GuardedPhysical<P> {
+    fn try_write_bytes_physical(&self, physical_address: u64, buffer: &[u8]) -> Option<()> {
+        if !self.map.contains(physical_address, buffer.len()) {
+            return None;
+        }
+        self.inner.try_write_bytes_physical(physical_address, buffer)
+    }
+}
+
+impl<P: TranslatePhysical> TranslatePhysical for GuardedPhysical<P> {
+    fn physical_address(&self, virtual_address: u64) -> Option<u64> {
+        self.inner.physical_address(virtual_address)
+    }
+
+    fn translate(&self, virtual_address: u64) -> Result<Translation, TranslationError> {
+        self.inner.translate(virtual_address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    const IOMEM: &str = "\
+00000000-00000fff : Reserved
+00001000-0009fbff : System RAM
+0009fc00-0009ffff : Reserved
+000f0000-000fffff : System ROM
+00100000-bffdffff : System RAM
+  01000000-01e03816 : Kernel code
+  02000000-0226efff : Kernel rodata
+bffe0000-bfffffff : Reserved
+febd0000-febd0fff : 0000:00:02.0
+  febd0000-febd0fff : bochs-drm
+100000000-23fffffff : System RAM
+";
+
+    /// Physical memory that records every read
+    struct RecordingPhysical {
+        reads: Mutex<Vec<MemoryRange>>,
+    }
+
+    impl PhysicalMemoryRead for RecordingPhysical {
+        fn try_read_bytes_physical_into(&self, physical_address: u64, buffer: &mut [u8]) -> Option<()> {
+            self.reads.lock().unwrap().push(physical_address..physical_address + buffer.len() as u64);
+            buffer.fill(0xAA);
+            Some(())
+        }
+    }
+
+    impl PhysicalMemoryWrite for RecordingPhysical {
+        fn try_write_bytes_physical(&self, _physical_address: u64, _buffer: &[u8]) -> Option<()> {
+            Some(())
+        }
+    }
+
+    #[test]
+    fn test_sources() {
+        let map = PhysicalMemoryMap::from_iomem(IOMEM).unwrap();
+        assert_eq!(map.ranges(), [0x1000..0x9FC00, 0x10_0000..0xBFFE_0000, 0x1_0000_0000..0x2_4000_0000]);
+        assert!(map.contains(0x10_0000, 0x1000));
+        assert!(!map.contains(0x9F000, 0x1000));
+        assert!(!map.contains(0xFEBD_0000, 4));
+        assert_eq!(map.range_containing(0x2_3FFF_FFFF), Some(&(0x1_0000_0000..0x2_4000_0000)));
+        assert_eq!(map.range_containing(0x2_4000_0000), None);
+
+        let hidden = IOMEM.lines()
+            .map(|l| format!("{}00000000-00000000 : {}\n", " ".repeat(l.len() - l.trim_start().len()), l.split_once(" : ").unwrap().1))
+            .collect::<String>();
+        assert!(matches!(PhysicalMemoryMap::from_iomem(&hidden), Err(PhysicalMapError::AddressesHidden)));
+        assert!(matches!(PhysicalMemoryMap::from_iomem("00001000 : System RAM"), Err(PhysicalMapError::InvalidLine { line: 1, .. })));
+
+        let e820 = PhysicalMemoryMap::from_entries([
+            MemoryMapEntry::e820(0, 0x9FC00, 1),
+            MemoryMapEntry::e820(0x9FC00, 0x400, 2),
+            MemoryMapEntry::e820(0x10_0000, 0x10_0000, 1),
+            MemoryMapEntry::e820(0x20_0000, 0x10_0000, 1),
+            MemoryMapEntry::efi(0x30_0000, 0x10, 3),
+            MemoryMapEntry::efi(0xFEC0_0000, 1, 11),
+        ]);
+        assert_eq!(e820.ranges(), [0..0x9FC00, 0x10_0000..0x31_0000]);
+        assert_eq!(e820.total_size(), 0x9FC00 + 0x21_0000);
+    }
+
+    #[test]
+    fn test_dump_header() {
+        let mut header = b"PAGE".repeat(0x400);
+        header[4..8].copy_from_slice(b"DU64");
+        header[0x88..0x8C].copy_from_slice(&2u32.to_le_bytes());
+        for (i, value) in [1u64, 0x9E, 0x100, 0x100].iter().enumerate() {
+            header[0x98 + i * 8..0xA0 + i * 8].copy_from_slice(&value.to_le_bytes());
+        }
+        let map = PhysicalMemoryMap::from_dump_header(&header).unwrap();
+        assert_eq!(map.ranges(), [0x1000..0x9F000, 0x10_0000..0x20_0000]);
+
+        let mut header = b"PAGE".repeat(0x400);
+        header[4..8].copy_from_slice(b"DUMP");
+        header[0x64..0x68].copy_from_slice(&1u32.to_le_bytes());
+        header[0x6C..0x70].copy_from_slice(&0x10u32.to_le_bytes());
+        header[0x70..0x74].copy_from_slice(&0x20u32.to_le_bytes());
+        let map = PhysicalMemoryMap::from_dump_header(&header).unwrap();
+        assert_eq!((map.ranges().len(), map.ranges().first()), (1, Some(&(0x10000..0x30000))));
+
+        assert!(PhysicalMemoryMap::from_dump_header(&b"PAGE".repeat(0x400)).is_err());
+        assert!(PhysicalMemoryMap::from_dump_header(&header[..0x6C]).is_err());
+    }
+
+    #[test]
+    fn test_guarded_reads() {
+        let map = PhysicalMemoryMap::new([0x1000..0x3000, 0x4000..0x5000]);
+        let guarded = GuardedPhysical::new(RecordingPhysical { reads: Mutex::new(Vec::new()) }, map);
+
+        let mut buffer = [0x11u8; 0x2000];
+        assert!(guarded.try_read_bytes_physical_into(0x2800, &mut buffer).is_none());
+        assert!(guarded.inner().reads.lock().unwrap().is_empty());
+        assert!(guarded.try_read_bytes_physical_into(0x1000, &mut buffer).is_some());
+        assert!(guarded.try_write_bytes_physical(0x1000, &buffer).is_some());
+        assert!(guarded.try_write_bytes_physical(0x2800, &buffer[..0x1000]).is_none());
+
+        let guarded = guarded.zero_fill_holes(true);
+        guarded.inner().reads.lock().unwrap().clear();
+        assert!(guarded.try_read_bytes_physical_into(0x2800, &mut buffer).is_some());
+        assert_eq!(*guarded.inner().reads.lock().unwrap(), [0x2800..0x3000, 0x4000..0x4800]);
+        assert!(buffer[..0x800].iter().all(|&b| b == 0xAA));
+        assert!(buffer[0x800..0x1800].iter().all(|&b| b == 0));
+        assert!(buffer[0x1800..].iter().all(|&b| b == 0xAA));
+
+        guarded.inner().reads.lock().unwrap().clear();
+        assert!(guarded.try_read_bytes_physical_into(0x3000, &mut buffer[..0x1000]).is_none());
+        assert!(guarded.try_read_bytes_physical_into(0xFEE0_0000, &mut buffer[..4]).is_none());
+        assert!(guarded.try_read_bytes_physical_into(u64::MAX - 1, &mut buffer[..4]).is_none());
+        assert!(guarded.inner().reads.lock().unwrap().is_empty());
+
+        // writes never fill holes
+        assert!(guarded.try_write_bytes_physical(0x2800, &buffer[..0x1000]).is_none());
+    }
+}
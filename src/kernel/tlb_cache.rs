@@ -0,0 +1,441 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use crate::*;
+
+/// The number of translations a TlbCache holds by default
+pub const DEFAULT_TLB_CAPACITY: usize = 4096;
+
+const PAGE_SIZES: [u64; 3] = [0x1000, 0x20_0000, 0x4000_0000];
+const UPPER_LEVELS: [PageTableLevel; 4] = [PageTableLevel::Pt, PageTableLevel::Pd, PageTableLevel::Pdpt, PageTableLevel::Pml4];
+
+/// The hit and miss counts of a TlbCache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TlbStats {
+    /// Translations answered from the cache
+    pub hits: u64,
+    /// Translations that walked the page tables
+    pub misses: u64,
+    /// Misses whose walk started from a cached upper level entry
+    pub upper_level_hits: u64,
+    /// Translations dropped to stay within the capacity
+    pub evictions: u64,
+}
+
+impl TlbStats {
+    /// Returns the fraction of translations answered from the cache, or 0 if nothing was translated
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+/// A map that drops the oldest inserted entry once it is full
+struct BoundedMap<K, V> {
+    map: HashMap<K, V>,
+    order: VecDeque<K>,
+    capacity: usize,
+}
+
+impl<K: Copy + Eq + std::hash::Hash, V> BoundedMap<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self { map: HashMap::new(), order: VecDeque::new(), capacity }
+    }
+
+    /// Inserts an entry and returns the number of entries evicted
+    fn insert(&mut self, key: K, value: V) -> u64 {
+        if self.capacity == 0 {
+            return 0;
+        }
+        if self.map.insert(key, value).is_some() {
+            return 0;
+        }
+        self.order.push_back(key);
+
+        let mut evicted = 0;
+        while self.map.len() > self.capacity {
+            let Some(oldest) = self.order.pop_front() else { break };
+            self.map.remove(&oldest);
+            evicted += 1;
+        }
+        evicted
+    }
+
+    fn retain(&mut self, mut f: impl FnMut(&K) -> bool) {
+        self.map.retain(|k, _| f(k));
+        self.order.retain(|k| f(k));
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+        self.order.clear();
+    }
+}
+
+/// (directory table base, page size, virtual address of the page)
+type TlbKey = (u64, u64, u64);
+/// (directory table base, level of the next table, virtual address bits above that table)
+type UpperKey = (u64, PageTableLevel, u64);
+
+/// A cached translation of a whole page. The entries are shared so hits only copy them when
+/// the caller asks for a full Translation
+struct CachedPage {
+    base: u64,
+    page_size: u64,
+    flags: Option<PageFlags>,
+    entries: Arc<[PageTableEntry]>,
+}
+
+impl CachedPage {
+    /// Returns the physical address of virtual_address and the number of bytes left in the page
+    fn physical_range(&self, virtual_address: u64) -> (u64, u64) {
+        let offset = virtual_address & (self.page_size - 1);
+        (self.base + offset, self.page_size - offset)
+    }
+
+    fn translation(&self, virtual_address: u64) -> Translation {
+        Translation {
+            physical_address: self.physical_range(virtual_address).0,
+            page_size: self.page_size,
+            flags: self.flags,
+            entries: self.entries.to_vec(),
+        }
+    }
+}
+
+struct TlbState {
+    translations: BoundedMap<TlbKey, CachedPage>,
+    upper: BoundedMap<UpperKey, Vec<PageTableEntry>>,
+    stats: TlbStats,
+}
+
+/// Wraps a PageTableWalk and caches the translation of every page it walks, keyed by the
+/// directory table base so switching address spaces on the inner walker does not return
+/// translations of another process.
+///
+/// Optionally the non-leaf entries of each walk are cached as well, like the paging-structure
+/// caches of the processor, so a miss on a page next to a cached one only reads the last level.
+///
+/// Nothing is invalidated when the page tables change. Call flush_page, flush_address_space
+/// or flush when pages are remapped or paged out
+pub struct TlbCache<T> {
+    inner: T,
+    state: Mutex<TlbState>,
+}
+
+impl<T: PageTableWalk> TlbCache<T> {
+    /// Creates a cache of DEFAULT_TLB_CAPACITY translations without an upper level cache
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            state: Mutex::new(TlbState {
+                translations: BoundedMap::new(DEFAULT_TLB_CAPACITY),
+                upper: BoundedMap::new(0),
+                stats: TlbStats::default(),
+            }),
+        }
+    }
+
+    /// Sets the maximum number of cached translations. Large pages take one entry
+    pub fn capacity(self, capacity: usize) -> Self {
+        self.state.lock().unwrap().translations = BoundedMap::new(capacity);
+        self
+    }
+
+    /// Caches up to capacity upper level entries, which are the PML4, PDPT and PD entries
+    /// that lead to a table. Disabled by default
+    pub fn upper_level_cache(self, capacity: usize) -> Self {
+        self.state.lock().unwrap().upper = BoundedMap::new(capacity);
+        self
+    }
+
+    /// Drops every cached translation and upper level entry
+    pub fn flush(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.translations.clear();
+        state.upper.clear();
+    }
+
+    /// Drops everything cached for the address space with the directory table base dtb
+    pub fn flush_address_space(&self, dtb: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.translations.retain(|&(key_dtb, _, _)| key_dtb != dtb);
+        state.upper.retain(|&(key_dtb, _, _)| key_dtb != dtb);
+    }
+
+    /// Drops the cached translation of the page containing virtual_address in the current address
+    /// space, and the upper level entries used to reach it
+    pub fn flush_page(&self, virtual_address: u64) {
+        let dtb = self.inner.directory_table_base();
+        let mut state = self.state.lock().unwrap();
+        state.translations.retain(|&(key_dtb, size, page)| key_dtb != dtb || page != virtual_address & !(size - 1));
+        state.upper.retain(|&(key_dtb, level, prefix)| key_dtb != dtb || prefix != upper_prefix(virtual_address, level));
+    }
+
+    /// Returns the hit and miss counts since the cache was created or the counts were reset
+    pub fn stats(&self) -> TlbStats {
+        self.state.lock().unwrap().stats
+    }
+
+    pub fn reset_stats(&self) {
+        self.state.lock().unwrap().stats = TlbStats::default();
+    }
+
+    /// Returns a reference to the inner walker
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner walker, for example to change its address space
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Consumes the cache and returns the inner walker
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn cached<R>(&self, virtual_address: u64, f: impl FnOnce(&CachedPage) -> R) -> Option<R> {
+        let dtb = self.inner.directory_table_base();
+        let mut state = self.state.lock().unwrap();
+        let translation = PAGE_SIZES.iter()
+            .find_map(|&size| state.translations.map.get(&(dtb, size, virtual_address & !(size - 1))));
+        let result = translation.map(f);
+        match result {
+            Some(_) => state.stats.hits += 1,
+            None => state.stats.misses += 1,
+        }
+        result
+    }
+
+    fn walk(&self, virtual_address: u64) -> Result<Translation, TranslationError> {
+        let dtb = self.inner.directory_table_base();
+        let upper = {
+            let mut state = self.state.lock().unwrap();
+            let upper = UPPER_LEVELS.iter()
+                .find_map(|&level| state.upper.map.get(&(dtb, level, upper_prefix(virtual_address, level))))
+                .cloned();
+            if upper.is_some() {
+                state.stats.upper_level_hits += 1;
+            }
+            upper
+        };
+
+        let translation = self.inner.translate_from(virtual_address, upper.unwrap_or_default())?;
+
+        let mut state = self.state.lock().unwrap();
+        let size = translation.page_size;
+        let page = CachedPage {
+            base: translation.physical_address & !(size - 1),
+            page_size: size,
+            flags: translation.flags,
+            entries: Arc::from(translation.entries.as_slice()),
+        };
+        let mut evicted = state.translations.insert((dtb, size, virtual_address & !(size - 1)), page);
+
+        // every entry before the leaf leads to the table of the next level
+        let walked = &translation.entries[..translation.entries.len().saturating_sub(1)];
+        for (i, entry) in walked.iter().enumerate() {
+            let level = match entry.level {
+                PageTableLevel::Pml5 => PageTableLevel::Pml4,
+                PageTableLevel::Pml4 => PageTableLevel::Pdpt,
+                PageTableLevel::Pdpt => PageTableLevel::Pd,
+                PageTableLevel::Pd | PageTableLevel::Pt => PageTableLevel::Pt,
+            };
+            evicted += state.upper.insert((dtb, level, upper_prefix(virtual_address, level)), walked[..=i].to_vec());
+        }
+        state.stats.evictions += evicted;
+
+        Ok(translation)
+    }
+}
+
+/// Returns the bits of virtual_address that select the table at level
+fn upper_prefix(virtual_address: u64, level: PageTableLevel) -> u64 {
+    virtual_address >> (level.shift() + 9)
+}
+
+impl<T: PageTableWalk> TranslatePhysical for TlbCache<T> {
+    fn physical_address(&self, virtual_address: u64) -> Option<u64> {
+        self.physical_range(virtual_address).map(|(physical_address, _)| physical_address)
+    }
+
+    fn translate(&self, virtual_address: u64) -> Result<Translation, TranslationError> {
+        match self.cached(virtual_address, |page| page.translation(virtual_address)) {
+            Some(translation) => Ok(translation),
+            None => self.walk(virtual_address),
+        }
+    }
+
+    fn physical_range(&self, virtual_address: u64) -> Option<(u64, u64)> {
+        match self.cached(virtual_address, |page| page.physical_range(virtual_address)) {
+            Some(range) => Some(range),
+            None => self.walk(virtual_address).ok().map(|t| (t.physical_address, t.remaining_in_page())),
+        }
+    }
+}
+
+impl<T: PageTableWalk> PageTableWalk for TlbCache<T> {
+    fn directory_table_base(&self) -> u64 {
+        self.inner.directory_table_base()
+    }
+
+    fn translate_from(&self, virtual_address: u64, entries: Vec<PageTableEntry>) -> Result<Translation, TranslationError> {
+        self.inner.translate_from(virtual_address, entries)
+    }
+}
+
+impl<T: PageTableWalk + PhysicalMemoryRead> PhysicalMemoryRead for TlbCache<T> {
+    fn try_read_bytes_physical_into(&self, physical_address: u64, buffer: &mut [u8]) -> Option<()> {
+        self.inner.try_read_bytes_physical_into(physical_address, buffer)
+    }
+}
+
+impl<T: PageTableWalk + PhysicalMemoryWrite> PhysicalMemoryWrite for TlbCache<T> {
+    fn try_write_bytes_physical(&self, physical_address: u64, buffer: &[u8]) -> Option<()> {
+        self.inner.try_write_bytes_physical(physical_address, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::page_table::tests::PageTableBuilder;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Counts the page table entries read from physical memory
+    struct CountingPhysical {
+        memory: MemoryBuffer<Vec<u8>>,
+        reads: AtomicU32,
+    }
+
+    impl PhysicalMemoryRead for CountingPhysical {
+        fn try_read_bytes_physical_into(&self, physical_address: u64, buffer: &mut [u8]) -> Option<()> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            self.memory.try_read_bytes_physical_into(physical_address, buffer)
+        }
+    }
+
+    fn walker(builder: PageTableBuilder) -> PageTableWalker<CountingPhysical> {
+        PageTableWalker::new(CountingPhysical { memory: builder.memory, reads: AtomicU32::new(0) }, builder.dtb)
+    }
+
+    fn reads(tlb: &TlbCache<PageTableWalker<CountingPhysical>>) -> u32 {
+        tlb.inner().inner().reads.swap(0, Ordering::SeqCst)
+    }
+
+    #[test]
+    fn test_hits_and_flush() {
+        let mut builder = PageTableBuilder::new(0x20000, PagingMode::FourLevel);
+        builder.map_page(0x1000, 0x10000);
+        builder.map_page(0x2000, 0x11000);
+        builder.map(0x60_0000, 0x20_0000 | 0x1, 0x20_0000);
+
+        let tlb = TlbCache::new(walker(builder));
+        assert_eq!(tlb.physical_address(0x1234), Some(0x10234));
+        assert_eq!(reads(&tlb), 4);
+        assert_eq!(tlb.physical_address(0x1FFF), Some(0x10FFF));
+        assert_eq!(tlb.translate(0x1008).unwrap().physical_address, 0x10008);
+        assert_eq!(tlb.physical_range(0x1FF0), Some((0x10FF0, 0x10)));
+        assert_eq!(reads(&tlb), 0);
+        assert_eq!(tlb.translate(0x1008).unwrap().entries, tlb.inner().translate(0x1008).unwrap().entries);
+        assert_eq!(reads(&tlb), 4);
+
+        // one entry covers the whole large page
+        assert_eq!(tlb.physical_address(0x60_0010), Some(0x20_0010));
+        assert_eq!(tlb.physical_address(0x7F_FFF0), Some(0x3F_FFF0));
+        assert_eq!(reads(&tlb), 3);
+
+        // failed translations are not cached
+        assert_eq!(tlb.physical_address(0x3000), None);
+        assert_eq!(tlb.physical_address(0x3000), None);
+        assert_eq!(reads(&tlb), 8);
+
+        let stats = tlb.stats();
+        assert_eq!((stats.hits, stats.misses), (5, 4));
+        assert_eq!(stats.hit_rate(), 5.0 / 9.0);
+
+        tlb.flush_page(0x1FFF);
+        assert_eq!(tlb.physical_address(0x2000), Some(0x11000));
+        assert_eq!(tlb.physical_address(0x1000), Some(0x10000));
+        assert_eq!(tlb.physical_address(0x60_0000), Some(0x20_0000));
+        assert_eq!(reads(&tlb), 8);
+
+        tlb.flush();
+        tlb.reset_stats();
+        tlb.physical_address(0x1000);
+        assert_eq!(tlb.stats(), TlbStats { misses: 1, ..Default::default() });
+    }
+
+    #[test]
+    fn test_address_spaces() {
+        let mut builder = PageTableBuilder::new(0x40000, PagingMode::FourLevel);
+        builder.map_page(0x1000, 0x10000);
+        let first = builder.dtb;
+        builder.dtb = 0x20000;
+        builder.map_page(0x1000, 0x11000);
+        let second = builder.dtb;
+
+        let mut tlb = TlbCache::new(walker(builder));
+        tlb.inner_mut().set_dtb(first);
+        assert_eq!(tlb.physical_address(0x1000), Some(0x10000));
+        tlb.inner_mut().set_dtb(second);
+        assert_eq!(tlb.physical_address(0x1000), Some(0x11000));
+        assert_eq!(tlb.stats().misses, 2);
+
+        tlb.flush_address_space(second);
+        assert_eq!(tlb.physical_address(0x1000), Some(0x11000));
+        tlb.inner_mut().set_dtb(first);
+        assert_eq!(tlb.physical_address(0x1000), Some(0x10000));
+        assert_eq!((tlb.stats().hits, tlb.stats().misses), (1, 3));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut builder = PageTableBuilder::new(0x40000, PagingMode::FourLevel);
+        for i in 0..8 {
+            builder.map_page(0x1000 * i, 0x10000 + 0x1000 * i);
+        }
+
+        let tlb = TlbCache::new(walker(builder)).capacity(4);
+        for i in 0..8 {
+            tlb.physical_address(0x1000 * i);
+        }
+        assert_eq!(tlb.stats().evictions, 4);
+
+        // the oldest pages were evicted
+        tlb.physical_address(0x7000);
+        tlb.physical_address(0);
+        assert_eq!((tlb.stats().hits, tlb.stats().misses), (1, 9));
+    }
+
+    #[test]
+    fn test_upper_level_cache() {
+        let mut builder = PageTableBuilder::new(0x40000, PagingMode::FourLevel);
+        for i in 0..4 {
+            builder.map_page(0x1000 * i, 0x10000 + 0x1000 * i);
+        }
+        builder.map_page(0x4000_0000, 0x18000);
+
+        let tlb = TlbCache::new(walker(builder)).upper_level_cache(16);
+        assert_eq!(tlb.physical_address(0x10), Some(0x10010));
+        assert_eq!(reads(&tlb), 4);
+
+        // the PD entry is cached, so only the PTE is read
+        let translation = tlb.translate(0x3010).unwrap();
+        assert_eq!(translation.physical_address, 0x13010);
+        assert_eq!(translation.entries.len(), 4);
+        assert_eq!(reads(&tlb), 1);
+
+        // another 1GiB region shares only the PML4 entry
+        assert_eq!(tlb.physical_address(0x4000_0123), Some(0x18123));
+        assert_eq!(reads(&tlb), 3);
+        assert_eq!(tlb.stats().upper_level_hits, 2);
+
+        tlb.flush_page(0x2000);
+        assert_eq!(tlb.physical_address(0x2000), Some(0x12000));
+        assert_eq!(reads(&tlb), 4);
+    }
+}
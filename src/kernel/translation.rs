@@ -0,0 +1,111 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// The hardware flags of an x86-64 page table entry
+    pub struct PageFlags: u64 {
+        const PRESENT = 1 << 0;
+        const WRITABLE = 1 << 1;
+        const USER = 1 << 2;
+        const WRITE_THROUGH = 1 << 3;
+        const CACHE_DISABLE = 1 << 4;
+        const ACCESSED = 1 << 5;
+        const DIRTY = 1 << 6;
+        /// Set in PDPT and PD entries that map 1GiB and 2MiB pages
+        const LARGE_PAGE = 1 << 7;
+        const GLOBAL = 1 << 8;
+        const NO_EXECUTE = 1 << 63;
+    }
+}
+
+impl PageFlags {
+    /// Combines the flags of every level of a translation. A page is only writable and user
+    /// accessible if every level allows it, and it is not executable if any level forbids it.
+    /// The other flags come from the last entry
+    pub fn effective(entries: &[PageTableEntry]) -> Self {
+        let Some(leaf) = entries.last() else { return Self::empty() };
+        let mut flags = Self::from_bits_truncate(leaf.value);
+        for entry in entries {
+            let entry = Self::from_bits_truncate(entry.value);
+            flags &= entry | !(Self::WRITABLE | Self::USER);
+            flags |= entry & Self::NO_EXECUTE;
+        }
+        flags
+    }
+}
+
+/// A level of the x86-64 page tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PageTableLevel {
+    Pml5,
+    Pml4,
+    Pdpt,
+    Pd,
+    Pt,
+}
+
+impl PageTableLevel {
+    /// Returns the size of the memory mapped by one entry at this level
+    pub fn entry_size(self) -> u64 {
+        1 << self.shift()
+    }
+
+    /// Returns the position of the index bits for this level in a virtual address
+    pub fn shift(self) -> u32 {
+        match self {
+            Self::Pml5 => 48,
+            Self::Pml4 => 39,
+            Self::Pdpt => 30,
+            Self::Pd => 21,
+            Self::Pt => 12,
+        }
+    }
+}
+
+/// An entry read while translating an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageTableEntry {
+    pub level: PageTableLevel,
+    /// The physical address of the entry
+    pub address: u64,
+    /// The raw value of the entry
+    pub value: u64,
+}
+
+/// A successful translation of a virtual address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Translation {
+    pub physical_address: u64,
+    /// The size of the page containing the address
+    pub page_size: u64,
+    /// The effective flags of the page, or None if the translator does not know them
+    pub flags: Option<PageFlags>,
+    /// The entry read at each level from the top of the page tables down. Empty if the
+    /// translator does not walk page tables
+    pub entries: Vec<PageTableEntry>,
+}
+
+impl Translation {
+    /// Returns the number of bytes after the address that are in the same page
+    pub fn remaining_in_page(&self) -> u64 {
+        self.page_size - (self.physical_address & (self.page_size - 1))
+    }
+}
+
+/// The reason a virtual address could not be translated
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TranslationError {
+    /// The entry at this level is empty
+    NotPresent { level: PageTableLevel, entry: u64 },
+    /// The PTE is not present but describes a page that is paged out, in transition
+    /// or backed by a prototype PTE
+    PagedOut { entry: u64 },
+    /// The entry at this level has reserved bits set
+    InvalidEntry { level: PageTableLevel, entry: u64 },
+    /// The page table at this level could not be read from physical memory
+    ReadFailed { level: PageTableLevel, address: u64 },
+    /// The address is not canonical for the paging mode, so it cannot be mapped
+    NonCanonical { address: u64 },
+    /// The translator could not translate the address and does not know why
+    Unmapped,
+}
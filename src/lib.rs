@@ -25,14 +25,47 @@ pub use render::DrawExt;
 #[macro_use]
 pub mod tests;
 
+mod cached_modules;
+mod dyn_process;
+mod fault_injector;
+mod forward;
+mod memory_buffer;
 mod memory_protection;
+mod metered;
+mod mock;
 mod pid_util;
+mod process_list;
+mod process_supervisor;
+mod protection_guard;
+mod record;
+mod remote_allocation;
+mod remote_arena;
+mod retrying;
+mod shared_process;
 mod slice_impl;
-
+mod symbols;
+mod throttled;
+
+pub use cached_modules::*;
+pub use dyn_process::*;
+pub use fault_injector::*;
+pub use memory_buffer::*;
+pub use metered::*;
+pub use mock::*;
 pub use pid_util::*;
+pub use process_list::*;
+pub use process_supervisor::*;
+pub use protection_guard::*;
+pub use record::*;
+pub use remote_allocation::*;
+pub use remote_arena::*;
+pub use retrying::*;
+pub use shared_process::*;
 pub use slice_impl::*;
+pub use symbols::*;
+pub use throttled::*;
 
-pub use memory_protection::MemoryProtection;
+pub use memory_protection::{MemoryProtection, ParseMemoryProtectionError};
 
 extern crate alloc;
 
@@ -227,7 +260,7 @@ impl<T: MemoryWrite> MemoryWriteExt for T {}
 impl MemoryWriteExt for dyn MemoryWrite {}
 
 /// Represents a single process module with a name, base, and size
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[repr(C)]
 pub struct Module {
     pub name: String,
@@ -240,6 +273,79 @@ impl Module {
     pub fn memory_range(&self) -> MemoryRange {
         self.base..(self.base + self.size)
     }
+
+    /// Returns the file name of the module without any directories
+    pub fn base_name(&self) -> &str {
+        base_name(&self.name)
+    }
+
+    /// Returns true if the module has the specified name. The comparison is case insensitive.
+    /// If both names are full paths they must match entirely, otherwise only the file names are compared
+    pub fn matches_name(&self, name: &str) -> bool {
+        names_match(&self.name, name)
+    }
+}
+
+fn base_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+fn is_path(name: &str) -> bool {
+    name.contains(['/', '\\'])
+}
+
+fn names_match(a: &str, b: &str) -> bool {
+    if is_path(a) && is_path(b) {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        base_name(a).to_lowercase() == base_name(b).to_lowercase()
+    }
+}
+
+/// A module along with metadata that not every backend can provide
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleInfo {
+    pub module: Module,
+    /// The full path of the module on disk
+    pub path: Option<String>,
+    /// The address of the entry point of the module
+    pub entry_point: Option<u64>,
+    /// The TimeDateStamp from the PE header
+    pub timestamp: Option<u32>,
+    /// The GNU build ID of an ELF image or the CodeView GUID and age of a PE image
+    pub build_id: Option<Vec<u8>>,
+    /// True if this is the main image of the process
+    pub is_main: bool,
+}
+
+impl ModuleInfo {
+    /// Creates module info without any metadata
+    pub fn new(module: Module) -> Self {
+        Self { module, path: None, entry_point: None, timestamp: None, build_id: None, is_main: false }
+    }
+
+    /// Returns true if the module has the specified name or path. If the path of the module
+    /// is known, a full path has to match it entirely
+    pub fn matches_name(&self, name: &str) -> bool {
+        match &self.path {
+            Some(path) if is_path(name) => names_match(path, name),
+            _ => self.module.matches_name(name),
+        }
+    }
+}
+
+impl From<Module> for ModuleInfo {
+    fn from(module: Module) -> Self {
+        Self::new(module)
+    }
+}
+
+impl core::ops::Deref for ModuleInfo {
+    type Target = Module;
+
+    fn deref(&self) -> &Module {
+        &self.module
+    }
 }
 
 /// Represents a type that has access to a process's modules
@@ -249,16 +355,49 @@ pub trait ModuleList {
     /// provide a single module based on the name, this function should panic
     fn get_module_list(&self) -> Vec<Module>;
 
-    /// Returns a single module by name.
+    /// Returns a single module by name or path. See Module::matches_name for how names are compared.
     /// If the module name does not exist, returns None
     fn get_module(&self, name: &str) -> Option<Module> {
         self.get_module_list()
             .into_iter()
-            .find(|m| m.name.to_lowercase() == name.to_lowercase())
+            .find(|m| m.matches_name(name))
     }
 
     /// Gets the main module from the process.
     fn get_main_module(&self) -> Module;
+
+    /// Returns a list of all modules with any extra metadata the implementor can provide.
+    /// By default only is_main is filled in
+    fn get_module_info_list(&self) -> Vec<ModuleInfo> {
+        let main_base = self.get_main_module().base;
+        self.get_module_list()
+            .into_iter()
+            .map(|module| ModuleInfo { is_main: module.base == main_base, ..ModuleInfo::new(module) })
+            .collect()
+    }
+
+    /// Returns a single module with its metadata by name or path
+    fn get_module_info(&self, name: &str) -> Option<ModuleInfo> {
+        self.get_module_info_list()
+            .into_iter()
+            .find(|m| m.matches_name(name))
+    }
+
+    /// Returns the module that contains the address
+    fn module_for_address(&self, address: u64) -> Option<Module> {
+        self.get_module_list()
+            .into_iter()
+            .find(|m| m.memory_range().contains(&address))
+    }
+
+    /// Formats an address relative to the module that contains it, for example `ntdll.dll+0x1234`.
+    /// Addresses outside of every module are formatted as plain hex
+    fn format_address(&self, address: u64) -> String {
+        match self.module_for_address(address) {
+            Some(module) => format!("{}+{:#X}", module.base_name(), address - module.base),
+            None => format!("{:#X}", address),
+        }
+    }
 }
 
 #[non_exhaustive]
@@ -275,6 +414,20 @@ pub trait MemoryProtect {
     fn set_protection(&self, range: MemoryRange, protection: MemoryProtection) -> Result<MemoryProtection, MemoryProtectError>;
 }
 
+/// A contiguous range of memory where every page has the same protection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub range: MemoryRange,
+    pub protection: MemoryProtection,
+}
+
+/// Represents a type that can query the layout of a process's memory
+pub trait MemoryRegions {
+    /// Returns the region containing the address.
+    /// If the address is not mapped, returns None
+    fn query_region(&self, address: u64) -> Option<MemoryRegion>;
+}
+
 #[non_exhaustive]
 #[derive(Debug)]
 pub enum MemoryAllocateError {
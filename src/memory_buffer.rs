@@ -0,0 +1,229 @@
+use core::cell::RefCell;
+use crate::*;
+
+/// A buffer of bytes that lives at a base address. Reads and writes use the
+/// real addresses of the memory rather than offsets into the buffer, so data
+/// dumped from a process can be read back at the addresses it was dumped from.
+///
+/// The buffer can own its data (`Vec<u8>`, `Box<[u8]>`) or borrow it (`&[u8]`, `&mut [u8]`).
+/// Writing is only supported when the data is mutable.
+pub struct MemoryBuffer<B> {
+    base: u64,
+    data: RefCell<B>,
+}
+
+impl<B: AsRef<[u8]>> MemoryBuffer<B> {
+    /// Creates a new buffer that starts at the base address. Panics if the buffer would end
+    /// past the end of the address space
+    pub fn new(base: u64, data: B) -> Self {
+        assert!(base.checked_add(data.as_ref().len() as u64).is_some(), "the buffer must end within the address space");
+        Self { base, data: RefCell::new(data) }
+    }
+
+    /// Returns the address of the first byte in the buffer
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    /// Returns the length of the buffer in bytes
+    pub fn len(&self) -> usize {
+        self.data.borrow().as_ref().len()
+    }
+
+    /// Returns true if the buffer contains no bytes
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the memory range covered by the buffer. The end cannot overflow because new
+    /// rejects buffers that would end past the address space
+    pub fn memory_range(&self) -> MemoryRange {
+        self.base..(self.base + self.len() as u64)
+    }
+
+    /// Consumes the buffer and returns the underlying data
+    pub fn into_inner(self) -> B {
+        self.data.into_inner()
+    }
+
+    /// Returns the offset into the data of a read or write of len bytes at address,
+    /// or None if any part of the access falls outside of the buffer
+    fn offset_of(&self, address: u64, len: usize) -> Option<usize> {
+        let offset = usize::try_from(address.checked_sub(self.base)?).ok()?;
+        if offset.checked_add(len)? > self.len() {
+            return None;
+        }
+        Some(offset)
+    }
+}
+
+impl MemoryBuffer<Vec<u8>> {
+    /// Dumps a memory range from a reader into an owned buffer based at the start of the range
+    pub fn dump(reader: &(impl MemoryRead + ?Sized), range: MemoryRange) -> Option<Self> {
+        let base = range.start;
+        reader.dump_memory(range).map(|data| Self::new(base, data))
+    }
+}
+
+impl<B: AsRef<[u8]>> MemoryRead for MemoryBuffer<B> {
+    fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        let offset = self.offset_of(address, buffer.len())?;
+        buffer.copy_from_slice(&self.data.borrow().as_ref()[offset..offset + buffer.len()]);
+
+        Some(())
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> MemoryWrite for MemoryBuffer<B> {
+    fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
+        let offset = self.offset_of(address, buffer.len())?;
+        self.data.borrow_mut().as_mut()[offset..offset + buffer.len()].copy_from_slice(buffer);
+
+        Some(())
+    }
+}
+
+/// Exposes a window of memory from another reader or writer. Any access that is not
+/// entirely inside of the window is rejected.
+///
+/// By default the window uses the same addresses as the inner type. A rebased view
+/// maps the start of the window to a different address in the inner type, which can be
+/// used to read a dump at the address it was taken from.
+pub struct View<M> {
+    inner: M,
+    range: MemoryRange,
+    inner_base: u64,
+}
+
+impl<M> View<M> {
+    /// Creates a view of the range of memory in inner
+    pub fn new(inner: M, range: MemoryRange) -> Self {
+        let inner_base = range.start;
+        Self { inner, range, inner_base }
+    }
+
+    /// Creates a view that restricts inner to the memory of a module
+    pub fn module(inner: M, module: &Module) -> Self {
+        Self::new(inner, module.memory_range())
+    }
+
+    /// Creates a view of range where range.start corresponds to inner_base in inner
+    pub fn rebased(inner: M, range: MemoryRange, inner_base: u64) -> Self {
+        Self { inner, range, inner_base }
+    }
+
+    /// Returns the memory range of the view
+    pub fn memory_range(&self) -> MemoryRange {
+        self.range.clone()
+    }
+
+    /// Returns a reference to the inner type
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    /// Consumes the view and returns the inner type
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    /// Translates an access of len bytes at address into the address in the inner type,
+    /// or None if the access is not entirely inside of the view
+    fn translate(&self, address: u64, len: usize) -> Option<u64> {
+        let end = address.checked_add(len as u64)?;
+        if address < self.range.start || end > self.range.end {
+            return None;
+        }
+        (address - self.range.start).checked_add(self.inner_base)
+    }
+}
+
+impl<M: MemoryRead> MemoryRead for View<M> {
+    fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        let address = self.translate(address, buffer.len())?;
+        self.inner.try_read_bytes_into(address, buffer)
+    }
+}
+
+impl<M: MemoryWrite> MemoryWrite for View<M> {
+    fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
+        let address = self.translate(address, buffer.len())?;
+        self.inner.try_write_bytes(address, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u64 = 0x7FF6_0000_0000;
+
+    #[test]
+    fn test_buffer_read() {
+        let data: Vec<u8> = (0..0x20).collect();
+        let buffer = MemoryBuffer::new(BASE, &data[..]);
+
+        assert_eq!(buffer.try_read_bytes(BASE, 4), Some(vec![0, 1, 2, 3]));
+        assert_eq!(buffer.try_read_bytes(BASE + 0x1C, 4), Some(vec![0x1C, 0x1D, 0x1E, 0x1F]));
+        assert_eq!(buffer.try_read::<u16>(BASE + 2), Some(0x0302));
+        assert!(buffer.try_read_bytes(BASE + 0x1D, 4).is_none());
+        assert!(buffer.try_read_bytes(BASE - 1, 1).is_none());
+        assert!(buffer.try_read_bytes(0, 1).is_none());
+        assert!(buffer.try_read_bytes(u64::MAX, 2).is_none());
+    }
+
+    #[test]
+    fn test_buffer_write() {
+        let buffer = MemoryBuffer::new(BASE, vec![0u8; 0x10]);
+
+        buffer.try_write(BASE + 4, &0xDEADBEEFu32).unwrap();
+        assert_eq!(buffer.read::<u32>(BASE + 4), 0xDEADBEEF);
+        assert!(buffer.try_write_bytes(BASE + 0xE, &[1, 2, 3]).is_none());
+        assert_eq!(&buffer.into_inner()[4..8], &0xDEADBEEFu32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_buffer_end_of_address_space() {
+        let buffer = MemoryBuffer::new(u64::MAX - 4, vec![0xAAu8; 4]);
+        assert_eq!(buffer.memory_range(), u64::MAX - 4..u64::MAX);
+        assert_eq!(buffer.try_read_bytes(u64::MAX - 1, 1), Some(vec![0xAA]));
+    }
+
+    #[test]
+    #[should_panic(expected = "the buffer must end within the address space")]
+    fn test_buffer_past_address_space() {
+        MemoryBuffer::new(u64::MAX - 4, vec![0u8; 8]);
+    }
+
+    #[test]
+    fn test_buffer_dump() {
+        let source = MemoryBuffer::new(BASE, (0..0x40).collect::<Vec<u8>>());
+        let dump = MemoryBuffer::dump(&source, BASE + 0x10..BASE + 0x20).unwrap();
+
+        assert_eq!(dump.memory_range(), BASE + 0x10..BASE + 0x20);
+        assert_eq!(dump.read::<u8>(BASE + 0x10), 0x10);
+        assert!(dump.try_read_bytes(BASE, 1).is_none());
+    }
+
+    #[test]
+    fn test_view_bounds() {
+        let source = MemoryBuffer::new(BASE, vec![0xCCu8; 0x100]);
+        let view = View::new(&source, BASE + 0x10..BASE + 0x20);
+
+        assert!(view.try_read_bytes(BASE + 0x10, 0x10).is_some());
+        assert!(view.try_read_bytes(BASE + 0x0F, 1).is_none());
+        assert!(view.try_read_bytes(BASE + 0x1F, 2).is_none());
+        assert!(view.try_write_bytes(BASE + 0x18, &[1, 2]).is_some());
+        assert!(view.try_write_bytes(BASE + 0x20, &[1]).is_none());
+        assert_eq!(source.read::<u16>(BASE + 0x18), 0x0201);
+    }
+
+    #[test]
+    fn test_view_rebased() {
+        let dump: Vec<u8> = (0..0x10).collect();
+        let view = View::rebased(&dump[..], BASE..BASE + 0x10, 0);
+
+        assert_eq!(view.try_read_bytes(BASE + 4, 2), Some(vec![4, 5]));
+        assert!(view.try_read_bytes(4, 2).is_none());
+    }
+}
@@ -26,4 +26,284 @@ bitflags! {
         /// Sets all pages to be write-combined.
         const WRITECOMBINE = 0x400;
     }
-}
\ No newline at end of file
+}
+
+const PROT_READ: i32 = 0x1;
+const PROT_WRITE: i32 = 0x2;
+const PROT_EXEC: i32 = 0x4;
+
+/// Flags that modify the access of a protection rather than granting access
+const MODIFIERS: [(MemoryProtection, &str); 3] = [
+    (MemoryProtection::GUARD, "guard"),
+    (MemoryProtection::NOCACHE, "nocache"),
+    (MemoryProtection::WRITECOMBINE, "writecombine"),
+];
+
+impl MemoryProtection {
+    /// Returns the protection that grants the specified access. Windows has no write-only
+    /// protection, so write access always includes read access.
+    pub fn from_access(read: bool, write: bool, execute: bool, copy_on_write: bool) -> Self {
+        match (read || write, write, execute, copy_on_write && write) {
+            (false, _, false, _) => Self::NOACCESS,
+            (false, _, true, _) => Self::EXECUTE,
+            (true, false, false, _) => Self::READONLY,
+            (true, false, true, _) => Self::EXECUTE_READ,
+            (true, true, false, false) => Self::READWRITE,
+            (true, true, false, true) => Self::WRITECOPY,
+            (true, true, true, false) => Self::EXECUTE_READWRITE,
+            (true, true, true, true) => Self::EXECUTE_WRITECOPY,
+        }
+    }
+
+    /// Returns true if the memory can be read
+    pub fn is_readable(&self) -> bool {
+        self.intersects(
+            Self::READONLY | Self::READWRITE | Self::WRITECOPY
+                | Self::EXECUTE_READ | Self::EXECUTE_READWRITE | Self::EXECUTE_WRITECOPY
+        )
+    }
+
+    /// Returns true if the memory can be written, including copy-on-write memory
+    pub fn is_writable(&self) -> bool {
+        self.intersects(Self::READWRITE | Self::WRITECOPY | Self::EXECUTE_READWRITE | Self::EXECUTE_WRITECOPY)
+    }
+
+    /// Returns true if the memory can be executed
+    pub fn is_executable(&self) -> bool {
+        self.intersects(Self::EXECUTE | Self::EXECUTE_READ | Self::EXECUTE_READWRITE | Self::EXECUTE_WRITECOPY)
+    }
+
+    /// Returns true if the memory is a guard page
+    pub fn is_guard(&self) -> bool {
+        self.contains(Self::GUARD)
+    }
+
+    /// Returns true if writing to the memory creates a private copy of the page
+    pub fn is_copy_on_write(&self) -> bool {
+        self.intersects(Self::WRITECOPY | Self::EXECUTE_WRITECOPY)
+    }
+
+    /// Converts POSIX PROT_* flags into a protection. PROT_WRITE without PROT_READ becomes
+    /// READWRITE because there is no write-only protection
+    pub fn from_posix(prot: i32) -> Self {
+        Self::from_access(prot & PROT_READ != 0, prot & PROT_WRITE != 0, prot & PROT_EXEC != 0, false)
+    }
+
+    /// Converts the protection into POSIX PROT_* flags. Copy-on-write becomes PROT_WRITE and
+    /// modifiers such as GUARD are dropped
+    pub fn to_posix(&self) -> i32 {
+        let mut prot = 0;
+        if self.is_readable() {
+            prot |= PROT_READ;
+        }
+        if self.is_writable() {
+            prot |= PROT_WRITE;
+        }
+        if self.is_executable() {
+            prot |= PROT_EXEC;
+        }
+        prot
+    }
+
+    /// Parses the permissions column of /proc/pid/maps, for example `r-xp` or `rw-s`.
+    /// Writable private mappings become READWRITE rather than WRITECOPY since that is
+    /// how they behave to the process that owns them
+    pub fn from_maps_perms(perms: &str) -> Option<Self> {
+        let perms = perms.as_bytes();
+        if perms.len() != 4 || !matches!(perms[3], b'p' | b's') {
+            return None;
+        }
+        let flag = |i: usize, c: u8| match perms[i] {
+            b'-' => Some(false),
+            n if n == c => Some(true),
+            _ => None,
+        };
+        Some(Self::from_access(flag(0, b'r')?, flag(1, b'w')?, flag(2, b'x')?, false))
+    }
+
+    /// Converts the protection into the permissions column of /proc/pid/maps.
+    /// Every mapping is reported as private
+    pub fn to_maps_perms(&self) -> String {
+        let mut perms = self.access_string();
+        if self.is_copy_on_write() {
+            perms.replace_range(1..2, "w");
+        }
+        perms.push('p');
+        perms
+    }
+
+    /// Returns the `rwx` part of the display string. Copy-on-write memory uses `c` instead of `w`
+    fn access_string(&self) -> String {
+        let mut s = String::with_capacity(3);
+        s.push(if self.is_readable() { 'r' } else { '-' });
+        s.push(match (self.is_writable(), self.is_copy_on_write()) {
+            (true, true) => 'c',
+            (true, false) => 'w',
+            _ => '-',
+        });
+        s.push(if self.is_executable() { 'x' } else { '-' });
+        s
+    }
+}
+
+/// Displays the protection as `rwx` text where `c` in place of `w` means copy-on-write,
+/// followed by `+guard`, `+nocache` or `+writecombine` when those flags are set
+impl core::fmt::Display for MemoryProtection {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.access_string())?;
+        for (flag, name) in MODIFIERS {
+            if self.contains(flag) {
+                write!(f, "+{}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseMemoryProtectionError(String);
+
+impl core::fmt::Display for ParseMemoryProtectionError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "invalid memory protection: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseMemoryProtectionError {}
+
+/// Parses the text created by Display. A `p` or `s` after the access is also accepted so
+/// the permissions column of /proc/pid/maps can be parsed directly
+impl core::str::FromStr for MemoryProtection {
+    type Err = ParseMemoryProtectionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseMemoryProtectionError(s.to_string());
+        let mut parts = s.split('+');
+        let mut access = parts.next().unwrap();
+        if access.len() == 4 {
+            access = access.strip_suffix(['p', 's']).ok_or_else(err)?;
+        }
+
+        let access = access.as_bytes();
+        if access.len() != 3 {
+            return Err(err());
+        }
+        let read = match access[0] {
+            b'r' => true,
+            b'-' => false,
+            _ => return Err(err()),
+        };
+        let (write, copy_on_write) = match access[1] {
+            b'w' => (true, false),
+            b'c' => (true, true),
+            b'-' => (false, false),
+            _ => return Err(err()),
+        };
+        let execute = match access[2] {
+            b'x' => true,
+            b'-' => false,
+            _ => return Err(err()),
+        };
+
+        let mut protection = Self::from_access(read, write, execute, copy_on_write);
+        for modifier in parts {
+            let (flag, _) = MODIFIERS.iter().find(|(_, name)| *name == modifier).ok_or_else(err)?;
+            protection |= *flag;
+        }
+        Ok(protection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every combination of the flags, including combinations no OS would return
+    fn all_combinations() -> impl Iterator<Item=MemoryProtection> {
+        let bits: Vec<u32> = (0..32).map(|i| 1 << i).filter(|b| MemoryProtection::all().bits() & b != 0).collect();
+        (0u32..1 << bits.len()).map(move |mask| {
+            let bits = bits.iter().enumerate().filter(|(i, _)| mask & (1 << i) != 0).fold(0, |acc, (_, b)| acc | b);
+            MemoryProtection::from_bits(bits).unwrap()
+        })
+    }
+
+    fn access(p: MemoryProtection) -> (bool, bool, bool, bool) {
+        (p.is_readable(), p.is_writable(), p.is_executable(), p.is_copy_on_write())
+    }
+
+    #[test]
+    fn test_queries() {
+        use MemoryProtection as P;
+        let cases = [
+            (P::NONE, "---"),
+            (P::NOACCESS, "---"),
+            (P::READONLY, "r--"),
+            (P::READWRITE, "rw-"),
+            (P::WRITECOPY, "rc-"),
+            (P::EXECUTE, "--x"),
+            (P::EXECUTE_READ, "r-x"),
+            (P::EXECUTE_READWRITE, "rwx"),
+            (P::EXECUTE_WRITECOPY, "rcx"),
+            (P::READWRITE | P::GUARD, "rw-+guard"),
+            (P::READONLY | P::NOCACHE | P::WRITECOMBINE, "r--+nocache+writecombine"),
+        ];
+        for (protection, text) in cases {
+            assert_eq!(protection.to_string(), text);
+            assert_eq!(text.parse::<MemoryProtection>().unwrap().to_string(), text);
+        }
+        assert!(P::READWRITE.is_writable() && !P::READWRITE.is_copy_on_write());
+        assert!((P::READWRITE | P::GUARD).is_guard());
+        assert!(!P::NOACCESS.is_readable());
+    }
+
+    #[test]
+    fn test_every_combination() {
+        for protection in all_combinations() {
+            let (read, write, execute, cow) = access(protection);
+            assert!(!write || read, "{:?} is writable but not readable", protection);
+            assert!(!cow || write);
+            assert_eq!(protection.is_guard(), protection.contains(MemoryProtection::GUARD));
+
+            // display and parse keeps the access and modifiers
+            let parsed: MemoryProtection = protection.to_string().parse().unwrap();
+            assert_eq!(access(parsed), access(protection), "{:?}", protection);
+            for (flag, _) in MODIFIERS {
+                assert_eq!(parsed.contains(flag), protection.contains(flag));
+            }
+
+            // posix keeps read, write and execute
+            let prot = protection.to_posix();
+            assert_eq!((prot & PROT_READ != 0, prot & PROT_WRITE != 0, prot & PROT_EXEC != 0), (read, write, execute));
+            assert_eq!(access(MemoryProtection::from_posix(prot)), (read, write, execute, false));
+
+            // maps keeps read, write and execute
+            let perms = protection.to_maps_perms();
+            assert_eq!(access(MemoryProtection::from_maps_perms(&perms).unwrap()), (read, write, execute, false));
+            assert_eq!(perms.parse::<MemoryProtection>().unwrap().to_posix(), prot);
+        }
+    }
+
+    #[test]
+    fn test_posix() {
+        for prot in 0..8 {
+            let protection = MemoryProtection::from_posix(prot);
+            let expected = if prot == PROT_WRITE || prot == PROT_WRITE | PROT_EXEC { prot | PROT_READ } else { prot };
+            assert_eq!(protection.to_posix(), expected);
+        }
+        assert_eq!(MemoryProtection::from_posix(PROT_READ | PROT_EXEC), MemoryProtection::EXECUTE_READ);
+        assert_eq!(MemoryProtection::from_posix(0), MemoryProtection::NOACCESS);
+    }
+
+    #[test]
+    fn test_maps_perms() {
+        assert_eq!(MemoryProtection::from_maps_perms("r-xp"), Some(MemoryProtection::EXECUTE_READ));
+        assert_eq!(MemoryProtection::from_maps_perms("rw-s"), Some(MemoryProtection::READWRITE));
+        assert_eq!(MemoryProtection::from_maps_perms("---p"), Some(MemoryProtection::NOACCESS));
+        assert_eq!(MemoryProtection::from_maps_perms("rwx"), None);
+        assert_eq!(MemoryProtection::from_maps_perms("xwrp"), None);
+        assert_eq!(MemoryProtection::WRITECOPY.to_maps_perms(), "rw-p");
+        assert_eq!("r-xp".parse::<MemoryProtection>(), Ok(MemoryProtection::EXECUTE_READ));
+        assert!("rwz".parse::<MemoryProtection>().is_err());
+        assert!("rw-+unknown".parse::<MemoryProtection>().is_err());
+    }
+}
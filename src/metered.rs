@@ -0,0 +1,264 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use crate::*;
+use crate::forward::forward_process_traits;
+
+/// The number of buckets in a LatencyHistogram
+pub const LATENCY_BUCKETS: usize = 24;
+
+/// A histogram of call latencies. Bucket 0 counts calls that took less than 1µs,
+/// bucket n counts calls that took between 2^(n-1)µs and 2^nµs, and the last bucket
+/// counts every call slower than that.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    pub buckets: [u64; LATENCY_BUCKETS],
+}
+
+impl LatencyHistogram {
+    /// Returns the upper bound of a bucket, or None for the last bucket
+    pub fn bucket_limit(bucket: usize) -> Option<Duration> {
+        (bucket < LATENCY_BUCKETS - 1).then(|| Duration::from_micros(1 << bucket))
+    }
+
+    fn bucket_of(latency: Duration) -> usize {
+        let micros = latency.as_micros() as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        bucket.min(LATENCY_BUCKETS - 1)
+    }
+
+    /// Returns the total number of calls in the histogram
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Returns the upper bound of the bucket containing the specified percentile (0.0 to 100.0)
+    /// of calls. Returns None if the histogram is empty or the percentile is in the last bucket
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let target = ((percentile / 100.0) * count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= target {
+                return Self::bucket_limit(bucket);
+            }
+        }
+        None
+    }
+}
+
+/// A snapshot of the counters for one kind of operation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OperationMetrics {
+    /// The number of calls made
+    pub calls: u64,
+    /// The number of bytes transferred by successful calls
+    pub bytes: u64,
+    /// The number of calls that failed
+    pub failures: u64,
+    /// The total time spent in the inner type
+    pub total_latency: Duration,
+    pub latency: LatencyHistogram,
+}
+
+impl OperationMetrics {
+    /// Returns the average latency of a call
+    pub fn average_latency(&self) -> Option<Duration> {
+        (self.calls > 0).then(|| Duration::from_nanos((self.total_latency.as_nanos() / self.calls as u128) as u64))
+    }
+}
+
+/// A snapshot of every counter in a Metered wrapper
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub reads: OperationMetrics,
+    pub writes: OperationMetrics,
+}
+
+#[derive(Default)]
+struct OperationCounters {
+    calls: AtomicU64,
+    bytes: AtomicU64,
+    failures: AtomicU64,
+    total_latency_ns: AtomicU64,
+    latency: [AtomicU64; LATENCY_BUCKETS],
+}
+
+impl OperationCounters {
+    fn record(&self, len: usize, success: bool, latency: Duration) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        if success {
+            self.bytes.fetch_add(len as u64, Ordering::Relaxed);
+        } else {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+        self.total_latency_ns.fetch_add(latency.as_nanos() as u64, Ordering::Relaxed);
+        self.latency[LatencyHistogram::bucket_of(latency)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> OperationMetrics {
+        let mut latency = LatencyHistogram::default();
+        for (bucket, counter) in latency.buckets.iter_mut().zip(&self.latency) {
+            *bucket = counter.load(Ordering::Relaxed);
+        }
+        OperationMetrics {
+            calls: self.calls.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            total_latency: Duration::from_nanos(self.total_latency_ns.load(Ordering::Relaxed)),
+            latency,
+        }
+    }
+
+    fn reset(&self) {
+        self.calls.store(0, Ordering::Relaxed);
+        self.bytes.store(0, Ordering::Relaxed);
+        self.failures.store(0, Ordering::Relaxed);
+        self.total_latency_ns.store(0, Ordering::Relaxed);
+        self.latency.iter().for_each(|n| n.store(0, Ordering::Relaxed));
+    }
+}
+
+/// Wraps a MemoryRead or MemoryWrite implementation and counts calls, bytes, failures and
+/// latency for reads and writes. With the `log` feature enabled every call can also be logged
+/// with its address and length.
+pub struct Metered<M> {
+    inner: M,
+    reads: OperationCounters,
+    writes: OperationCounters,
+    #[cfg(feature = "log")]
+    log_level: Option<log::Level>,
+}
+
+impl<M> Metered<M> {
+    pub fn new(inner: M) -> Self {
+        Self {
+            inner,
+            reads: OperationCounters::default(),
+            writes: OperationCounters::default(),
+            #[cfg(feature = "log")]
+            log_level: None,
+        }
+    }
+
+    /// Logs every read and write at the specified level
+    #[cfg(feature = "log")]
+    pub fn with_logging(mut self, level: log::Level) -> Self {
+        self.log_level = Some(level);
+        self
+    }
+
+    /// Returns a snapshot of the counters
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            reads: self.reads.snapshot(),
+            writes: self.writes.snapshot(),
+        }
+    }
+
+    /// Resets every counter to zero
+    pub fn reset(&self) {
+        self.reads.reset();
+        self.writes.reset();
+    }
+
+    /// Returns a reference to the inner type
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    /// Consumes the wrapper and returns the inner type
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    #[allow(unused_variables)]
+    fn log(&self, operation: &str, address: u64, len: usize, success: bool, latency: Duration) {
+        #[cfg(feature = "log")]
+        if let Some(level) = self.log_level {
+            log::log!(
+                level,
+                "{} of {:#X} bytes at {:#X} {} in {:?}",
+                operation, len, address, if success { "succeeded" } else { "failed" }, latency
+            );
+        }
+    }
+}
+
+impl<M: MemoryRead> MemoryRead for Metered<M> {
+    fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        let start = Instant::now();
+        let result = self.inner.try_read_bytes_into(address, buffer);
+        let latency = start.elapsed();
+
+        self.reads.record(buffer.len(), result.is_some(), latency);
+        self.log("read", address, buffer.len(), result.is_some(), latency);
+        result
+    }
+}
+
+impl<M: MemoryWrite> MemoryWrite for Metered<M> {
+    fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
+        let start = Instant::now();
+        let result = self.inner.try_write_bytes(address, buffer);
+        let latency = start.elapsed();
+
+        self.writes.record(buffer.len(), result.is_some(), latency);
+        self.log("write", address, buffer.len(), result.is_some(), latency);
+        result
+    }
+}
+
+forward_process_traits!(Metered.inner: ModuleList, ProcessInfo, MemoryAllocate, MemoryProtect, MemoryRegions);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters() {
+        let metered = Metered::new(MemoryBuffer::new(0x1000, vec![0u8; 0x100]));
+
+        metered.read::<u64>(0x1000);
+        metered.read::<u32>(0x1010);
+        assert!(metered.try_read::<u32>(0x2000).is_none());
+        metered.write(0x1000, &1u16);
+        assert!(metered.try_write(0x10FF, &1u16).is_none());
+
+        let metrics = metered.metrics();
+        assert_eq!((metrics.reads.calls, metrics.reads.bytes, metrics.reads.failures), (3, 12, 1));
+        assert_eq!((metrics.writes.calls, metrics.writes.bytes, metrics.writes.failures), (2, 2, 1));
+        assert_eq!(metrics.reads.latency.count(), 3);
+        assert!(metrics.reads.average_latency().is_some());
+
+        metered.reset();
+        assert_eq!(metered.metrics(), Metrics::default());
+    }
+
+    #[test]
+    fn test_histogram() {
+        assert_eq!(LatencyHistogram::bucket_of(Duration::from_nanos(500)), 0);
+        assert_eq!(LatencyHistogram::bucket_of(Duration::from_micros(1)), 1);
+        assert_eq!(LatencyHistogram::bucket_of(Duration::from_micros(3)), 2);
+        assert_eq!(LatencyHistogram::bucket_of(Duration::from_secs(3600)), LATENCY_BUCKETS - 1);
+
+        let mut histogram = LatencyHistogram::default();
+        histogram.buckets[1] = 90;
+        histogram.buckets[10] = 10;
+        assert_eq!(histogram.percentile(50.0), Some(Duration::from_micros(2)));
+        assert_eq!(histogram.percentile(99.0), Some(Duration::from_micros(1024)));
+        assert_eq!(LatencyHistogram::default().percentile(50.0), None);
+    }
+
+    #[test]
+    fn test_average_latency() {
+        let metrics = OperationMetrics { calls: 1 << 32, total_latency: Duration::from_secs(1 << 32), ..Default::default() };
+        assert_eq!(metrics.average_latency(), Some(Duration::from_secs(1)));
+        let metrics = OperationMetrics { calls: 3 << 32, total_latency: Duration::from_secs(6 << 32), ..Default::default() };
+        assert_eq!(metrics.average_latency(), Some(Duration::from_secs(2)));
+        assert_eq!(OperationMetrics::default().average_latency(), None);
+    }
+}
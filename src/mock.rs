@@ -0,0 +1,516 @@
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use crate::*;
+
+const PAGE_SIZE: u64 = 0x1000;
+const DEFAULT_ALLOCATION_BASE: u64 = 0x1000_0000;
+
+/// A single region of memory in a MockProcess
+#[derive(Debug, Clone)]
+pub struct MockRegion {
+    pub base: u64,
+    pub data: Vec<u8>,
+    pub protection: MemoryProtection,
+    /// The base of the region this region was split from by a protection change, or base if it was never split
+    pub allocation_base: u64,
+}
+
+impl MockRegion {
+    /// Returns the memory range of the region
+    pub fn memory_range(&self) -> MemoryRange {
+        self.base..(self.base + self.data.len() as u64)
+    }
+}
+
+struct MockState {
+    regions: BTreeMap<u64, MockRegion>,
+    modules: Vec<ModuleInfo>,
+    process_name: String,
+    pid: u32,
+    peb_base_address: u64,
+    next_allocation: u64,
+}
+
+/// A sparse in-memory address space that implements every process trait in memlib.
+/// Memory is made of regions at arbitrary addresses, each with a MemoryProtection that is
+/// enforced on reads and writes. Allocations, frees and protection changes update the regions
+/// the same way they would in a real process. All state is behind a lock, so a MockProcess
+/// can be shared between threads.
+pub struct MockProcess {
+    state: RwLock<MockState>,
+}
+
+impl Default for MockProcess {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockProcess {
+    /// Creates an empty address space named mock.exe
+    pub fn new() -> Self {
+        Self {
+            state: RwLock::new(MockState {
+                regions: BTreeMap::new(),
+                modules: Vec::new(),
+                process_name: "mock.exe".to_string(),
+                pid: 1,
+                peb_base_address: 0,
+                next_allocation: DEFAULT_ALLOCATION_BASE,
+            })
+        }
+    }
+
+    /// Sets the name returned by ProcessInfo::process_name
+    pub fn with_process_name(self, name: impl Into<String>) -> Self {
+        self.state.write().unwrap().process_name = name.into();
+        self
+    }
+
+    /// Sets the pid returned by ProcessInfo::pid
+    pub fn with_pid(self, pid: u32) -> Self {
+        self.state.write().unwrap().pid = pid;
+        self
+    }
+
+    /// Sets the address returned by ProcessInfo::peb_base_address
+    pub fn with_peb_base_address(self, peb_base_address: u64) -> Self {
+        self.state.write().unwrap().peb_base_address = peb_base_address;
+        self
+    }
+
+    /// Sets the address that MemoryAllocate starts searching for free memory at
+    pub fn with_allocation_base(self, base: u64) -> Self {
+        self.state.write().unwrap().next_allocation = base;
+        self
+    }
+
+    /// Maps a region of memory containing data at base with the specified protection.
+    /// Panics if the region overlaps an existing region
+    pub fn add_region(&self, base: u64, data: Vec<u8>, protection: MemoryProtection) {
+        let mut state = self.state.write().unwrap();
+        let range = base..(base + data.len() as u64);
+        assert!(state.is_free(range.clone()), "region {:#X?} overlaps an existing region", range);
+        state.regions.insert(base, MockRegion { base, data, protection, allocation_base: base });
+    }
+
+    /// Adds a module to the module list and maps a region for it containing data.
+    /// The first module added is the main module unless another module is marked as main
+    pub fn add_module(&self, name: impl Into<String>, base: u64, data: Vec<u8>, protection: MemoryProtection) -> Module {
+        let module = Module { name: name.into(), base, size: data.len() as u64 };
+        self.add_region(base, data, protection);
+        self.add_module_info(ModuleInfo::new(module.clone()));
+        module
+    }
+
+    /// Adds a module to the module list without mapping any memory for it
+    pub fn add_module_entry(&self, module: Module) {
+        self.add_module_info(ModuleInfo::new(module));
+    }
+
+    /// Adds a module with metadata to the module list without mapping any memory for it
+    pub fn add_module_info(&self, info: ModuleInfo) {
+        self.state.write().unwrap().modules.push(info);
+    }
+
+    /// Removes every module with the specified name from the module list.
+    /// The memory of the module stays mapped
+    pub fn remove_module(&self, name: &str) {
+        self.state.write().unwrap().modules.retain(|m| !m.matches_name(name));
+    }
+
+    /// Returns a copy of every region in the address space ordered by address
+    pub fn regions(&self) -> Vec<MockRegion> {
+        self.state.read().unwrap().regions.values().cloned().collect()
+    }
+
+    /// Returns a copy of the region containing address
+    pub fn region_at(&self, address: u64) -> Option<MockRegion> {
+        let state = self.state.read().unwrap();
+        state.region_containing(address).map(|base| state.regions[&base].clone())
+    }
+
+    /// Runs f over every region covering range in order. Returns None if any part of
+    /// the range is unmapped or f returns None for a region.
+    fn for_each_region<S, F>(state: S, range: MemoryRange, mut f: F) -> Option<()>
+        where
+            S: core::ops::Deref<Target=MockState>,
+            F: FnMut(&MockRegion, MemoryRange) -> Option<()>,
+    {
+        let mut address = range.start;
+        while address < range.end {
+            let region = &state.regions[&state.region_containing(address)?];
+            let end = region.memory_range().end.min(range.end);
+            f(region, address..end)?;
+            address = end;
+        }
+        Some(())
+    }
+
+    /// Clears the guard flag of the region containing address, the same way an
+    /// access to a guard page does on Windows
+    fn trip_guard(&self, address: u64) {
+        let mut state = self.state.write().unwrap();
+        if let Some(base) = state.region_containing(address) {
+            let region = state.regions.get_mut(&base).unwrap();
+            region.protection.remove(MemoryProtection::GUARD);
+        }
+    }
+}
+
+impl MockState {
+    fn region_containing(&self, address: u64) -> Option<u64> {
+        let (base, region) = self.regions.range(..=address).next_back()?;
+        region.memory_range().contains(&address).then_some(*base)
+    }
+
+    fn is_free(&self, range: MemoryRange) -> bool {
+        !self.regions.values().any(|r| {
+            let region = r.memory_range();
+            region.start < range.end && range.start < region.end
+        })
+    }
+
+    /// Splits the region containing address so that a region begins at address
+    fn split_at(&mut self, address: u64) {
+        let base = match self.region_containing(address) {
+            Some(base) if base != address => base,
+            _ => return,
+        };
+        let region = self.regions.get_mut(&base).unwrap();
+        let data = region.data.split_off((address - base) as usize);
+        let (protection, allocation_base) = (region.protection, region.allocation_base);
+        self.regions.insert(address, MockRegion { base: address, data, protection, allocation_base });
+    }
+}
+
+fn page_align(range: MemoryRange) -> MemoryRange {
+    (range.start & !(PAGE_SIZE - 1))..((range.end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1))
+}
+
+impl MemoryRead for MockProcess {
+    fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        let range = address..address.checked_add(buffer.len() as u64)?;
+        let mut guard = None;
+        let result = Self::for_each_region(self.state.read().unwrap(), range, |region, range| {
+            if region.protection.contains(MemoryProtection::GUARD) {
+                guard = Some(range.start);
+                return None;
+            }
+            if !region.protection.is_readable() {
+                return None;
+            }
+            let src = (range.start - region.base) as usize..(range.end - region.base) as usize;
+            let dst = (range.start - address) as usize..(range.end - address) as usize;
+            buffer[dst].copy_from_slice(&region.data[src]);
+            Some(())
+        });
+
+        if let Some(address) = guard {
+            self.trip_guard(address);
+        }
+        result
+    }
+}
+
+impl MemoryWrite for MockProcess {
+    fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
+        let range = address..address.checked_add(buffer.len() as u64)?;
+        let mut state = self.state.write().unwrap();
+
+        // check the whole range before writing so a failed write doesn't modify memory
+        let mut guard = None;
+        let valid = Self::for_each_region(&*state, range.clone(), |region, range| {
+            if region.protection.contains(MemoryProtection::GUARD) {
+                guard = Some(range.start);
+                return None;
+            }
+            region.protection.is_writable().then_some(())
+        });
+        if valid.is_none() {
+            drop(state);
+            if let Some(address) = guard {
+                self.trip_guard(address);
+            }
+            return None;
+        }
+
+        let mut address = range.start;
+        while address < range.end {
+            let base = state.region_containing(address).unwrap();
+            let region = state.regions.get_mut(&base).unwrap();
+            let end = region.memory_range().end.min(range.end);
+            let src = (address - range.start) as usize..(end - range.start) as usize;
+            region.data[(address - base) as usize..(end - base) as usize].copy_from_slice(&buffer[src]);
+            address = end;
+        }
+
+        Some(())
+    }
+}
+
+impl ModuleList for MockProcess {
+    fn get_module_list(&self) -> Vec<Module> {
+        self.state.read().unwrap().modules.iter().map(|m| m.module.clone()).collect()
+    }
+
+    fn get_main_module(&self) -> Module {
+        self.get_module_info_list().into_iter()
+            .find(|m| m.is_main)
+            .expect("MockProcess has no modules")
+            .module
+    }
+
+    fn get_module_info_list(&self) -> Vec<ModuleInfo> {
+        let mut modules = self.state.read().unwrap().modules.clone();
+        if !modules.iter().any(|m| m.is_main) {
+            if let Some(first) = modules.first_mut() {
+                first.is_main = true;
+            }
+        }
+        modules
+    }
+}
+
+impl ProcessInfo for MockProcess {
+    fn process_name(&self) -> String {
+        self.state.read().unwrap().process_name.clone()
+    }
+
+    fn peb_base_address(&self) -> u64 {
+        self.state.read().unwrap().peb_base_address
+    }
+
+    fn pid(&self) -> u32 {
+        self.state.read().unwrap().pid
+    }
+}
+
+impl MemoryAllocate for MockProcess {
+    fn allocate(&self, size: u64, protection: MemoryProtection) -> Result<u64, MemoryAllocateError> {
+        if size == 0 {
+            return Err(MemoryAllocateError::Message("cannot allocate zero bytes".to_string()));
+        }
+        let size = page_align(0..size).end;
+
+        let mut state = self.state.write().unwrap();
+        let mut base = state.next_allocation;
+        while !state.is_free(base..base + size) {
+            let (_, region) = state.regions.range(..base + size).next_back().unwrap();
+            base = page_align(region.memory_range()).end;
+        }
+
+        state.regions.insert(base, MockRegion { base, data: vec![0u8; size as usize], protection, allocation_base: base });
+        state.next_allocation = base + size;
+        Ok(base)
+    }
+
+    /// Frees the allocation starting at base, including the regions it was split into by protection
+    /// changes. size must either be zero or the size of the allocation
+    fn free(&self, base: u64, size: u64) -> Result<(), MemoryAllocateError> {
+        let mut state = self.state.write().unwrap();
+        if state.regions.get(&base).map(|r| r.allocation_base) != Some(base) {
+            return Err(MemoryAllocateError::Message(format!("no allocation starts at {:#X}", base)));
+        }
+        let pieces: Vec<u64> = state.regions.values().filter(|r| r.allocation_base == base).map(|r| r.base).collect();
+        let allocation_size: u64 = pieces.iter().map(|b| state.regions[b].data.len() as u64).sum();
+        if size != 0 && size != allocation_size && page_align(0..size).end != allocation_size {
+            return Err(MemoryAllocateError::Message(format!(
+                "free size {:#X} does not match allocation size {:#X}", size, allocation_size
+            )));
+        }
+
+        for piece in pieces {
+            state.regions.remove(&piece);
+        }
+        Ok(())
+    }
+}
+
+impl MemoryProtect for MockProcess {
+    /// Changes the protection of every page in range. The range must be fully mapped.
+    /// Returns the protection of the first page in the range
+    fn set_protection(&self, range: MemoryRange, protection: MemoryProtection) -> Result<MemoryProtection, MemoryProtectError> {
+        if range.start >= range.end {
+            return Err(MemoryProtectError::InvalidMemoryRange(range));
+        }
+        let aligned = page_align(range.clone());
+
+        let mut state = self.state.write().unwrap();
+        if Self::for_each_region(&*state, aligned.clone(), |_, _| Some(())).is_none() {
+            return Err(MemoryProtectError::InvalidMemoryRange(range));
+        }
+
+        state.split_at(aligned.start);
+        state.split_at(aligned.end);
+
+        let old = state.regions[&aligned.start].protection;
+        for (_, region) in state.regions.range_mut(aligned) {
+            region.protection = protection;
+        }
+        Ok(old)
+    }
+}
+
+impl MemoryRegions for MockProcess {
+    fn query_region(&self, address: u64) -> Option<MemoryRegion> {
+        self.region_at(address).map(|r| MemoryRegion { range: r.memory_range(), protection: r.protection })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u64 = 0x140000000;
+
+    fn process() -> MockProcess {
+        let process = MockProcess::new()
+            .with_process_name("game.exe")
+            .with_pid(1234)
+            .with_peb_base_address(0x7FF00000);
+        process.add_module("game.exe", BASE, vec![0x90; 0x3000], MemoryProtection::EXECUTE_READ);
+        process.add_region(0x10000, vec![0; 0x1000], MemoryProtection::READWRITE);
+        process.add_region(0x11000, vec![0; 0x1000], MemoryProtection::READONLY);
+        process.add_region(0x20000, vec![0; 0x1000], MemoryProtection::NOACCESS);
+        process
+    }
+
+    #[test]
+    fn test_process_info() {
+        let process = process();
+        assert_eq!(process.process_name(), "game.exe");
+        assert_eq!(process.pid(), 1234);
+        assert_eq!(process.peb_base_address(), 0x7FF00000);
+        assert_eq!(process.get_main_module().base, BASE);
+        assert_eq!(process.get_module("GAME.EXE").unwrap().size, 0x3000);
+        assert!(process.get_module("ntdll.dll").is_none());
+    }
+
+    #[test]
+    fn test_module_lookup() {
+        let process = process();
+        process.add_module_info(ModuleInfo {
+            path: Some("C:\\Windows\\System32\\ntdll.dll".to_string()),
+            entry_point: Some(0x7FF800001000),
+            ..ModuleInfo::new(Module { name: "ntdll.dll".to_string(), base: 0x7FF800000000, size: 0x1000 })
+        });
+
+        assert_eq!(process.get_module("C:\\Windows\\System32\\NTDLL.DLL").unwrap().base, 0x7FF800000000);
+        assert!(process.get_module_info("c:\\windows\\system32\\ntdll.dll").is_some());
+        assert!(process.get_module_info("C:\\Other\\ntdll.dll").is_none());
+        assert!(process.get_module_info_list()[0].is_main);
+        assert!(!process.get_module_info("ntdll.dll").unwrap().is_main);
+
+        assert_eq!(process.module_for_address(BASE + 0x2FFF).unwrap().name, "game.exe");
+        assert!(process.module_for_address(BASE + 0x3000).is_none());
+        assert_eq!(process.format_address(0x7FF800000000 + 0x1234 - 0x1000), "ntdll.dll+0x234");
+        assert_eq!(process.format_address(0x1234), "0x1234");
+    }
+
+    #[test]
+    fn test_protection_enforced() {
+        let process = process();
+        assert_eq!(process.try_read::<u8>(BASE), Some(0x90));
+        assert!(process.try_write(BASE, &0u8).is_none());
+        assert!(process.try_write(0x10000, &1u32).is_some());
+        assert!(process.try_write(0x11000, &1u32).is_none());
+        assert!(process.try_read::<u32>(0x11000).is_some());
+        assert!(process.try_read::<u8>(0x20000).is_none());
+        assert!(process.try_read::<u8>(0x30000).is_none());
+    }
+
+    #[test]
+    fn test_access_across_regions() {
+        let process = process();
+        assert!(process.try_read_bytes(0x10FFE, 4).is_some());
+
+        // the write must not partially apply
+        assert!(process.try_write_bytes(0x10FFE, &[1, 2, 3, 4]).is_none());
+        assert_eq!(process.try_read_bytes(0x10FFE, 2), Some(vec![0, 0]));
+
+        process.set_protection(0x11000..0x12000, MemoryProtection::READWRITE).unwrap();
+        process.try_write_bytes(0x10FFE, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(process.try_read_bytes(0x10FFE, 4), Some(vec![1, 2, 3, 4]));
+
+        // gaps between regions fail
+        assert!(process.try_read_bytes(0x11FFF, 2).is_none());
+    }
+
+    #[test]
+    fn test_guard_page() {
+        let process = process();
+        process.add_region(0x30000, vec![0; 0x1000], MemoryProtection::READWRITE | MemoryProtection::GUARD);
+
+        assert!(process.try_read::<u8>(0x30000).is_none());
+        assert!(process.try_read::<u8>(0x30000).is_some());
+    }
+
+    #[test]
+    fn test_set_protection() {
+        let process = process();
+        let old = process.set_protection(BASE + 0x1000..BASE + 0x1800, MemoryProtection::EXECUTE_READWRITE).unwrap();
+        assert_eq!(old, MemoryProtection::EXECUTE_READ);
+
+        let regions = process.regions();
+        let protections: Vec<_> = regions.iter()
+            .filter(|r| r.base >= BASE)
+            .map(|r| (r.memory_range(), r.protection))
+            .collect();
+        assert_eq!(protections, vec![
+            (BASE..BASE + 0x1000, MemoryProtection::EXECUTE_READ),
+            (BASE + 0x1000..BASE + 0x2000, MemoryProtection::EXECUTE_READWRITE),
+            (BASE + 0x2000..BASE + 0x3000, MemoryProtection::EXECUTE_READ),
+        ]);
+
+        assert!(process.try_write(BASE + 0x1000, &0u8).is_some());
+        assert_eq!(process.read::<u8>(BASE + 0x1001), 0x90);
+
+        assert!(matches!(
+            process.set_protection(0x11000..0x13000, MemoryProtection::READWRITE),
+            Err(MemoryProtectError::InvalidMemoryRange(_))
+        ));
+    }
+
+    #[test]
+    fn test_allocate_free() {
+        let process = process().with_allocation_base(0x10000);
+        let a = process.allocate(0x10, MemoryProtection::READWRITE).unwrap();
+        let b = process.allocate(0x2000, MemoryProtection::READONLY).unwrap();
+
+        assert_eq!(a, 0x12000);
+        assert_eq!(b, 0x13000);
+        assert_eq!(process.region_at(a).unwrap().data.len(), 0x1000);
+        process.write(a, &5u64);
+        assert!(process.try_write(b, &5u64).is_none());
+
+        assert!(process.free(a + 1, 0).is_err());
+        assert!(process.free(a, 0x3000).is_err());
+        process.free(a, 0x10).unwrap();
+        process.free(b, 0).unwrap();
+        assert!(process.try_read::<u8>(a).is_none());
+        assert!(process.region_at(b).is_none());
+
+        // a protection change splits the allocation, but it is still freed as a whole
+        let c = process.allocate(0x3000, MemoryProtection::READWRITE).unwrap();
+        process.set_protection(c + 0x1000..c + 0x2000, MemoryProtection::READONLY).unwrap();
+        assert_eq!(process.region_at(c).unwrap().data.len(), 0x1000);
+        assert!(process.free(c + 0x1000, 0).is_err());
+        process.free(c, 0x3000).unwrap();
+        assert!(process.region_at(c + 0x2000).is_none());
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let process = std::sync::Arc::new(process());
+        let handles: Vec<_> = (0..4u64).map(|i| {
+            let process = process.clone();
+            std::thread::spawn(move || process.write(0x10000 + i * 8, &i))
+        }).collect();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+
+        for i in 0..4u64 {
+            assert_eq!(process.read::<u64>(0x10000 + i * 8), i);
+        }
+    }
+}
@@ -1,5 +1,10 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use crate::*;
 
+/// How often attach_wait checks for the process
+pub const ATTACH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 /// Represents a type that can attach to a process and return
 /// a struct that implements MemoryRead, MemoryWrite, and ModuleList
 pub trait ProcessAttach: Sized {
@@ -15,6 +20,36 @@ pub trait ProcessAttach: Sized {
 
     /// Attaches to the current process
     fn attach_current(&self) -> Self::ProcessType<'_>;
+
+    /// Attaches to a process of name process_name, polling until it is found or timeout has elapsed
+    fn attach_wait(&self, process_name: &str, timeout: Duration) -> Option<Self::ProcessType<'_>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(process) = self.attach(process_name) {
+                return Some(process);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            std::thread::sleep(ATTACH_POLL_INTERVAL.min(deadline - now));
+        }
+    }
+
+    /// Attaches to every process with the name process_name ignoring case.
+    /// Processes that exit before they can be attached to are skipped
+    fn attach_all(&self, process_name: &str) -> Vec<Self::ProcessType<'_>>
+        where Self: ProcessList {
+        self.attach_filtered(&ProcessFilter::case_insensitive(process_name))
+    }
+
+    /// Attaches to every process selected by filter
+    fn attach_filtered(&self, filter: &ProcessFilter) -> Vec<Self::ProcessType<'_>>
+        where Self: ProcessList {
+        self.find_processes(filter).into_iter()
+            .filter_map(|p| self.attach_pid(p.pid))
+            .collect()
+    }
 }
 
 /// Represents a type that can attach to a process and return
@@ -90,6 +125,8 @@ pub trait GetContext {
 pub enum MaybeOwned<'a, T> {
     Borrowed(&'a T),
     Owned(T),
+    /// Owned by an Arc so one API can be shared by several attached processes and threads
+    Shared(Arc<T>),
 }
 
 impl<'a, T> From<&'a T> for MaybeOwned<'a, T> {
@@ -109,6 +146,7 @@ impl<'a, T> AsRef<T> for MaybeOwned<'a, T> {
         match self {
             Self::Borrowed(n) => n,
             Self::Owned(n) => n,
+            Self::Shared(n) => n,
         }
     }
 }
@@ -118,7 +156,8 @@ impl<'a, T> MaybeOwned<'a, T> {
         where T: Clone {
         match self {
             Self::Borrowed(n) => n.clone(),
-            Self::Owned(n) => n
+            Self::Owned(n) => n,
+            Self::Shared(n) => Arc::try_unwrap(n).unwrap_or_else(|n| (*n).clone()),
         }
     }
 }
@@ -140,11 +179,15 @@ impl<'a, T> AttachedProcess<'a, T>
         Self { api: MaybeOwned::Borrowed(api), context }
     }
 
+    /// Creates a process that shares ownership of api, so it can be
+    /// sent to other threads without a lifetime
+    pub fn new_shared(api: Arc<T>, context: T::Context) -> AttachedProcess<'static, T>
+        where T: 'static {
+        AttachedProcess { api: MaybeOwned::Shared(api), context }
+    }
+
     pub fn api(&self) -> &T {
-        match &self.api {
-            MaybeOwned::Borrowed(api) => api,
-            MaybeOwned::Owned(api) => api
-        }
+        self.api.as_ref()
     }
 
     pub fn context(&self) -> &T::Context {
@@ -152,149 +195,180 @@ impl<'a, T> AttachedProcess<'a, T>
     }
 }
 
-/// A trait that mirrors the MemoryRead trait but reads from a PID instead of directly from the implementor.
-/// Note that the Pid type is not necessarily a Windows process ID. One may implement this using another form of identifier such as a dirbase.
-pub trait MemoryReadPid: GetContext {
-    /// Reads memory from the process with the given PID.
-    fn try_read_bytes_into_pid(&self, ctx: &Self::Context, address: u64, buffer: &mut [u8]) -> Option<()>;
-}
-
-impl<T> MemoryRead for AttachedProcess<'_, T>
-    where
-        T: MemoryReadPid
-{
-    fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
-        self.api().try_read_bytes_into_pid(self.context(), address, buffer)
-    }
-}
+/// Declares a trait that mirrors a process trait, with every method taking the context of
+/// the process as its first argument, and implements the original trait for AttachedProcess
+/// by forwarding to the mirror. Methods can have default bodies, which are kept in the mirror.
+///
+/// ```
+/// use memlib::*;
+///
+/// pub trait ThreadList {
+///     fn threads(&self) -> Vec<u32>;
+///     fn thread_count(&self) -> usize;
+/// }
+///
+/// memlib::mirror_pid_trait! {
+///     pub trait ThreadListPid mirrors ThreadList {
+///         fn threads_pid(&self, ctx: &Self::Context) -> Vec<u32> => threads;
+///
+///         fn thread_count_pid(&self, ctx: &Self::Context) -> usize => thread_count {
+///             self.threads_pid(ctx).len()
+///         }
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! mirror_pid_trait {
+    (
+        $(#[$attr:meta])*
+        $vis:vis trait $pid_trait:ident mirrors $trait:path {
+            $(
+                $(#[$fn_attr:meta])*
+                fn $pid_fn:ident(&$self:ident, $ctx:ident: &Self::Context $(, $arg:ident: $ty:ty)* $(,)?) $(-> $ret:ty)?
+                    => $fn:ident $($body:block)? $(;)?
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        $vis trait $pid_trait: $crate::GetContext {
+            $(
+                $crate::mirror_pid_trait!(@method
+                    [$(#[$fn_attr])* fn $pid_fn(&$self, $ctx: &Self::Context $(, $arg: $ty)*) $(-> $ret)?]
+                    $($body)?
+                );
+            )*
+        }
 
-/// A trait that mirrors the MemoryWrite trait but writes to a PID instead of directly from the implementor.
-/// Note that the Pid type is not necessarily a Windows process ID. One may implement this using another form of identifier such as a dirbase.
-pub trait MemoryWritePid: GetContext {
-    /// Writes memory to the process with the given PID.
-    fn try_write_bytes_pid(&self, ctx: &Self::Context, address: u64, buffer: &[u8]) -> Option<()>;
+        impl<T> $trait for $crate::AttachedProcess<'_, T>
+            where
+                T: $pid_trait,
+        {
+            $(
+                fn $fn(&self $(, $arg: $ty)*) $(-> $ret)? {
+                    <T as $pid_trait>::$pid_fn(self.api(), self.context() $(, $arg)*)
+                }
+            )*
+        }
+    };
+    (@method [$($signature:tt)*] $body:block) => {
+        $($signature)* $body
+    };
+    (@method [$($signature:tt)*]) => {
+        $($signature)*;
+    };
 }
 
-impl<T> MemoryWrite for AttachedProcess<'_, T>
-    where
-        T: MemoryWritePid,
-{
-    fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
-        self.api().try_write_bytes_pid(self.context(), address, buffer)
+mirror_pid_trait! {
+    /// A trait that mirrors the MemoryRead trait but reads from a PID instead of directly from the implementor.
+    /// Note that the Pid type is not necessarily a Windows process ID. One may implement this using another form of identifier such as a dirbase.
+    pub trait MemoryReadPid mirrors MemoryRead {
+        /// Reads memory from the process with the given PID.
+        fn try_read_bytes_into_pid(&self, ctx: &Self::Context, address: u64, buffer: &mut [u8]) -> Option<()>
+            => try_read_bytes_into;
     }
 }
 
-/// A trait that mirrors the ModuleList trait by gets information from a PID instead of directly from the implementor.
-/// Note that the Pid type is not necessarily a Windows process ID. One may implement this using another form of identifier such as a dirbase.
-pub trait ModuleListPid: GetContext {
-    /// Returns a list of all modules from a Pid. If the implementor can only
-    /// provide a single module based on the name, this function should panic
-    fn get_module_list(&self, pid: &Self::Context) -> Vec<Module>;
-
-    /// Returns a single module by name from the Pid.
-    /// If the module name does not exist, returns None
-    fn get_module(&self, pid: &Self::Context, name: &str) -> Option<Module> {
-        self.get_module_list(pid)
-            .into_iter()
-            .find(|m| m.name.to_lowercase() == name.to_lowercase())
+mirror_pid_trait! {
+    /// A trait that mirrors the MemoryWrite trait but writes to a PID instead of directly from the implementor.
+    /// Note that the Pid type is not necessarily a Windows process ID. One may implement this using another form of identifier such as a dirbase.
+    pub trait MemoryWritePid mirrors MemoryWrite {
+        /// Writes memory to the process with the given PID.
+        fn try_write_bytes_pid(&self, ctx: &Self::Context, address: u64, buffer: &[u8]) -> Option<()>
+            => try_write_bytes;
     }
-
-    /// Gets the main module from the Pid.
-    fn get_main_module(&self, pid: &Self::Context) -> Module;
 }
 
-impl<T> ModuleList for AttachedProcess<'_, T>
-    where
-        T: ModuleListPid,
-{
-    fn get_module_list(&self) -> Vec<Module> {
-        self.api().get_module_list(self.context())
-    }
-
-    fn get_module(&self, name: &str) -> Option<Module> {
-        self.api().get_module(self.context(), name)
-    }
+mirror_pid_trait! {
+    /// A trait that mirrors the ModuleList trait by gets information from a PID instead of directly from the implementor.
+    /// Note that the Pid type is not necessarily a Windows process ID. One may implement this using another form of identifier such as a dirbase.
+    pub trait ModuleListPid mirrors ModuleList {
+        /// Returns a list of all modules from a Pid. If the implementor can only
+        /// provide a single module based on the name, this function should panic
+        fn get_module_list(&self, pid: &Self::Context) -> Vec<Module>
+            => get_module_list;
+
+        /// Returns a single module by name from the Pid.
+        /// If the module name does not exist, returns None
+        fn get_module(&self, pid: &Self::Context, name: &str) -> Option<Module>
+            => get_module {
+            self.get_module_list(pid)
+                .into_iter()
+                .find(|m| m.matches_name(name))
+        }
 
-    fn get_main_module(&self) -> Module {
-        self.api().get_main_module(self.context())
+        /// Gets the main module from the Pid.
+        fn get_main_module(&self, pid: &Self::Context) -> Module
+            => get_main_module;
+
+        /// Returns a list of all modules from the Pid with any extra metadata the implementor can provide.
+        /// By default only is_main is filled in
+        fn get_module_info_list(&self, pid: &Self::Context) -> Vec<ModuleInfo>
+            => get_module_info_list {
+            let main_base = self.get_main_module(pid).base;
+            self.get_module_list(pid)
+                .into_iter()
+                .map(|module| ModuleInfo { is_main: module.base == main_base, ..ModuleInfo::new(module) })
+                .collect()
+        }
     }
 }
 
-/// A trait that mirrors the ProcessInfo trait by gets information from a PID instead of directly from the implementor.
-/// Note that the Pid type is not necessarily a Windows process ID. One may implement this using another form of identifier such as a dirbase.
-pub trait ProcessInfoPid: GetContext {
-    fn process_name(&self, pid: &Self::Context) -> String;
-    fn peb_base_address(&self, pid: &Self::Context) -> u64;
-    fn pid(&self, pid: &Self::Context) -> u32;
-}
-
-impl<T> ProcessInfo for AttachedProcess<'_, T>
-    where
-        T: ProcessInfoPid,
-{
-    fn process_name(&self) -> String {
-        self.api().process_name(self.context())
-    }
-
-    fn peb_base_address(&self) -> u64 {
-        self.api().peb_base_address(self.context())
-    }
-
-    fn pid(&self) -> u32 {
-        self.api().pid(self.context())
+mirror_pid_trait! {
+    /// A trait that mirrors the ProcessInfo trait by gets information from a PID instead of directly from the implementor.
+    /// Note that the Pid type is not necessarily a Windows process ID. One may implement this using another form of identifier such as a dirbase.
+    pub trait ProcessInfoPid mirrors ProcessInfo {
+        fn process_name(&self, pid: &Self::Context) -> String => process_name;
+        fn peb_base_address(&self, pid: &Self::Context) -> u64 => peb_base_address;
+        fn pid(&self, pid: &Self::Context) -> u32 => pid;
     }
 }
 
-pub trait MemoryAllocatePid: GetContext {
-    /// Allocates size bytes of memory in the process with the specified protection.
-    /// Returns the allocated memory or an error.
-    fn allocate_pid(&self, pid: &Self::Context, size: u64, protection: MemoryProtection) -> Result<u64, MemoryAllocateError>;
+mirror_pid_trait! {
+    pub trait MemoryAllocatePid mirrors MemoryAllocate {
+        /// Allocates size bytes of memory in the process with the specified protection.
+        /// Returns the allocated memory or an error.
+        fn allocate_pid(&self, pid: &Self::Context, size: u64, protection: MemoryProtection) -> Result<u64, MemoryAllocateError>
+            => allocate;
 
-    /// Frees allocated memory at the specified address and size.
-    fn free_pid(&self, pid: &Self::Context, base: u64, size: u64) -> Result<(), MemoryAllocateError>;
-}
-
-impl<T> MemoryAllocate for AttachedProcess<'_, T>
-    where
-        T: MemoryAllocatePid,
-{
-    fn allocate(&self, size: u64, protection: MemoryProtection) -> Result<u64, MemoryAllocateError> {
-        self.api().allocate_pid(self.context(), size, protection)
-    }
-
-    fn free(&self, base: u64, size: u64) -> Result<(), MemoryAllocateError> {
-        self.api().free_pid(self.context(), base, size)
+        /// Frees allocated memory at the specified address and size.
+        fn free_pid(&self, pid: &Self::Context, base: u64, size: u64) -> Result<(), MemoryAllocateError>
+            => free;
     }
 }
 
-pub trait MemoryProtectPid: GetContext {
-    /// Sets the protection of the memory range to the specified protection.
-    /// Returns the old memory protection or an error
-    fn set_protection_pid(&self, pid: &Self::Context, range: MemoryRange, protection: MemoryProtection) -> Result<MemoryProtection, MemoryProtectError>;
-}
-
-impl<T> MemoryProtect for AttachedProcess<'_, T>
-    where
-        T: MemoryProtectPid,
-{
-    fn set_protection(&self, range: MemoryRange, protection: MemoryProtection) -> Result<MemoryProtection, MemoryProtectError> {
-        self.api().set_protection_pid(self.context(), range, protection)
+mirror_pid_trait! {
+    pub trait MemoryProtectPid mirrors MemoryProtect {
+        /// Sets the protection of the memory range to the specified protection.
+        /// Returns the old memory protection or an error
+        fn set_protection_pid(&self, pid: &Self::Context, range: MemoryRange, protection: MemoryProtection) -> Result<MemoryProtection, MemoryProtectError>
+            => set_protection;
     }
 }
 
-/// A trait that mirrors the TranslatePhysical trait that translates a virtual
-/// address from a certain Context into a physical address
-#[cfg(feature = "kernel")]
-pub trait TranslatePhysicalPid: GetContext {
-    fn physical_address_pid(&self, ctx: &Self::Context, virtual_address: u64) -> Option<u64>;
+mirror_pid_trait! {
+    /// A trait that mirrors the MemoryRegions trait but queries a PID instead of the implementor.
+    /// Note that the Pid type is not necessarily a Windows process ID. One may implement this using another form of identifier such as a dirbase.
+    pub trait MemoryRegionsPid mirrors MemoryRegions {
+        /// Returns the region containing the address in the process
+        fn query_region_pid(&self, pid: &Self::Context, address: u64) -> Option<MemoryRegion>
+            => query_region;
+    }
 }
 
 #[cfg(feature = "kernel")]
-impl<T> TranslatePhysical for AttachedProcess<'_, T>
-    where
-        T: TranslatePhysicalPid
-{
-    fn physical_address(&self, virtual_address: u64) -> Option<u64> {
-        self.api().physical_address_pid(self.context(), virtual_address)
+mirror_pid_trait! {
+    /// A trait that mirrors the TranslatePhysical trait that translates a virtual
+    /// address from a certain Context into a physical address
+    pub trait TranslatePhysicalPid mirrors TranslatePhysical {
+        fn physical_address_pid(&self, ctx: &Self::Context, virtual_address: u64) -> Option<u64>
+            => physical_address;
+
+        /// Translates a virtual address from the Context. By default this uses physical_address_pid
+        /// and assumes 4KiB pages
+        fn translate_pid(&self, ctx: &Self::Context, virtual_address: u64) -> Result<Translation, TranslationError>
+            => translate {
+            let physical_address = self.physical_address_pid(ctx, virtual_address).ok_or(TranslationError::Unmapped)?;
+            Ok(Translation { physical_address, page_size: 0x1000, flags: None, entries: Vec::new() })
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,282 @@
+use std::time::SystemTime;
+
+/// A process returned by ProcessList
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessEntry {
+    pub pid: u32,
+    pub name: String,
+    /// The pid of the parent process, if known
+    pub parent_pid: Option<u32>,
+    /// The full path of the executable, if it can be read
+    pub exe_path: Option<String>,
+    /// The command line arguments including the program name. Empty if they cannot be read
+    pub cmdline: Vec<String>,
+    pub start_time: Option<SystemTime>,
+}
+
+/// Selects processes from a ProcessList
+pub enum ProcessFilter {
+    /// Matches the process name exactly
+    Exact(String),
+    /// Matches the process name ignoring ASCII case
+    CaseInsensitive(String),
+    /// Matches the process name against a pattern where `*` matches any number of characters
+    /// and `?` matches one character
+    Glob(String),
+    Predicate(Box<dyn Fn(&ProcessEntry) -> bool + Send + Sync>),
+}
+
+impl ProcessFilter {
+    pub fn exact(name: impl Into<String>) -> Self {
+        Self::Exact(name.into())
+    }
+
+    pub fn case_insensitive(name: impl Into<String>) -> Self {
+        Self::CaseInsensitive(name.into())
+    }
+
+    pub fn glob(pattern: impl Into<String>) -> Self {
+        Self::Glob(pattern.into())
+    }
+
+    pub fn predicate(f: impl Fn(&ProcessEntry) -> bool + Send + Sync + 'static) -> Self {
+        Self::Predicate(Box::new(f))
+    }
+
+    /// Returns true if the process is selected by the filter
+    pub fn matches(&self, process: &ProcessEntry) -> bool {
+        match self {
+            Self::Exact(name) => process.name == *name,
+            Self::CaseInsensitive(name) => process.name.eq_ignore_ascii_case(name),
+            Self::Glob(pattern) => glob_match(pattern.as_bytes(), process.name.as_bytes()),
+            Self::Predicate(f) => f(process),
+        }
+    }
+}
+
+impl core::fmt::Debug for ProcessFilter {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Exact(name) => f.debug_tuple("Exact").field(name).finish(),
+            Self::CaseInsensitive(name) => f.debug_tuple("CaseInsensitive").field(name).finish(),
+            Self::Glob(pattern) => f.debug_tuple("Glob").field(pattern).finish(),
+            Self::Predicate(_) => f.write_str("Predicate(..)"),
+        }
+    }
+}
+
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    // the position after the last * and the text position it is currently matched up to
+    let mut backtrack = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(c) if *c == b'?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    backtrack = Some((bp, bt + 1));
+                    p = bp;
+                    t = bt + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// Represents a type that can list the running processes
+#[auto_impl::auto_impl(&, & mut, Box)]
+pub trait ProcessList {
+    /// Returns every running process
+    fn process_list(&self) -> Vec<ProcessEntry>;
+
+    /// Returns every process selected by the filter
+    fn find_processes(&self, filter: &ProcessFilter) -> Vec<ProcessEntry> {
+        self.process_list().into_iter().filter(|p| filter.matches(p)).collect()
+    }
+
+    /// Returns the process with the specified pid
+    fn get_process(&self, pid: u32) -> Option<ProcessEntry> {
+        self.process_list().into_iter().find(|p| p.pid == pid)
+    }
+}
+
+/// Lists processes using the Linux /proc filesystem
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcFsProcessList;
+
+#[cfg(target_os = "linux")]
+impl ProcFsProcessList {
+    /// Returns the number of clock ticks per second that the kernel reports start times in.
+    /// Falls back to 100, the value on almost every Linux system, if sysconf fails
+    fn clock_ticks() -> u64 {
+        extern "C" {
+            fn sysconf(name: std::os::raw::c_int) -> std::os::raw::c_long;
+        }
+        const _SC_CLK_TCK: std::os::raw::c_int = 2;
+        match unsafe { sysconf(_SC_CLK_TCK) } {
+            ticks if ticks > 0 => ticks as u64,
+            _ => 100,
+        }
+    }
+
+    fn boot_time() -> Option<SystemTime> {
+        let stat = std::fs::read_to_string("/proc/stat").ok()?;
+        let btime = stat.lines().find_map(|l| l.strip_prefix("btime "))?.trim().parse().ok()?;
+        Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(btime))
+    }
+
+    fn read_process(pid: u32, boot_time: Option<SystemTime>, clock_ticks: u64) -> Option<ProcessEntry> {
+        let dir = std::path::Path::new("/proc").join(pid.to_string());
+        let (comm, parent_pid, start_ticks) = parse_stat(&std::fs::read_to_string(dir.join("stat")).ok()?)?;
+
+        let exe_path = std::fs::read_link(dir.join("exe")).ok()
+            .map(|p| p.to_string_lossy().into_owned());
+        let cmdline = std::fs::read(dir.join("cmdline")).unwrap_or_default()
+            .split(|b| *b == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+
+        // comm is truncated to 15 bytes, so the executable name is used when it is longer
+        let name = exe_path.as_deref()
+            .map(|p| p.rsplit('/').next().unwrap_or(p).trim_end_matches(" (deleted)"))
+            .filter(|exe| exe.len() > comm.len() && exe.starts_with(comm.as_str()))
+            .map_or(comm, String::from);
+
+        Some(ProcessEntry {
+            pid,
+            name,
+            parent_pid: Some(parent_pid).filter(|p| *p != 0),
+            exe_path,
+            cmdline,
+            start_time: boot_time.map(|b| b + std::time::Duration::from_millis(start_ticks * 1000 / clock_ticks)),
+        })
+    }
+}
+
+/// Parses the name, parent pid and start time from /proc/[pid]/stat.
+/// The name is in parentheses and can contain spaces and parentheses itself
+#[cfg(target_os = "linux")]
+fn parse_stat(stat: &str) -> Option<(String, u32, u64)> {
+    let (open, close) = (stat.find('(')?, stat.rfind(')')?);
+    if open > close {
+        return None;
+    }
+    let name = &stat[open + 1..close];
+    let fields: Vec<&str> = stat[close + 1..].split_whitespace().collect();
+    // fields are numbered from pid (1), so ppid (4) and starttime (22) are offset by 3
+    Some((name.to_string(), fields.get(1)?.parse().ok()?, fields.get(19)?.parse().ok()?))
+}
+
+#[cfg(target_os = "linux")]
+impl ProcessList for ProcFsProcessList {
+    fn process_list(&self) -> Vec<ProcessEntry> {
+        let (boot_time, clock_ticks) = (Self::boot_time(), Self::clock_ticks());
+        let Ok(entries) = std::fs::read_dir("/proc") else { return Vec::new() };
+        let mut processes: Vec<_> = entries
+            .filter_map(|e| e.ok()?.file_name().to_str()?.parse().ok())
+            .filter_map(|pid| Self::read_process(pid, boot_time, clock_ticks))
+            .collect();
+        processes.sort_by_key(|p| p.pid);
+        processes
+    }
+
+    fn get_process(&self, pid: u32) -> Option<ProcessEntry> {
+        Self::read_process(pid, Self::boot_time(), Self::clock_ticks())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    fn entry(pid: u32, name: &str) -> ProcessEntry {
+        ProcessEntry { pid, name: name.to_string(), parent_pid: None, exe_path: None, cmdline: Vec::new(), start_time: None }
+    }
+
+    #[test]
+    fn test_filters() {
+        let game = entry(1, "Game.exe");
+        assert!(ProcessFilter::exact("Game.exe").matches(&game));
+        assert!(!ProcessFilter::exact("game.exe").matches(&game));
+        assert!(ProcessFilter::case_insensitive("game.EXE").matches(&game));
+        assert!(ProcessFilter::predicate(|p| p.pid == 1).matches(&game));
+
+        assert!(ProcessFilter::glob("G*.exe").matches(&game));
+        assert!(ProcessFilter::glob("*").matches(&game));
+        assert!(ProcessFilter::glob("Gam?.*").matches(&game));
+        assert!(ProcessFilter::glob("*a*e*e").matches(&game));
+        assert!(!ProcessFilter::glob("*.dll").matches(&game));
+        assert!(!ProcessFilter::glob("Game").matches(&game));
+        assert!(!ProcessFilter::glob("Game.exe?").matches(&game));
+    }
+
+    struct FakeApi(Vec<ProcessEntry>);
+
+    impl GetContext for FakeApi {
+        type Context = u32;
+
+        fn get_context_from_name(&self, process_name: &str) -> Option<u32> {
+            self.0.iter().find(|p| p.name == process_name).map(|p| p.pid)
+        }
+
+        fn get_context_from_pid(&self, pid: u32) -> Option<u32> {
+            // pid 3 exits before it can be attached to
+            (pid != 3).then_some(pid)
+        }
+
+        fn get_current_context(&self) -> u32 {
+            0
+        }
+    }
+
+    impl ProcessList for FakeApi {
+        fn process_list(&self) -> Vec<ProcessEntry> {
+            self.0.clone()
+        }
+    }
+
+    #[test]
+    fn test_attach_all() {
+        let api = FakeApi(vec![entry(1, "game.exe"), entry(2, "GAME.EXE"), entry(3, "game.exe"), entry(4, "launcher.exe")]);
+        let pids: Vec<u32> = api.attach_all("Game.exe").iter().map(|p| p.context).collect();
+        assert_eq!(pids, [1, 2]);
+
+        let pids: Vec<u32> = api.attach_filtered(&ProcessFilter::glob("*.exe")).iter().map(|p| p.context).collect();
+        assert_eq!(pids, [1, 4]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_stat() {
+        let stat = "1234 (my (odd) proc) S 1 1234 1234 0 -1 4194560 100 0 0 0 5 3 0 0 20 0 1 0 98765 1000 100";
+        assert_eq!(parse_stat(stat), Some(("my (odd) proc".to_string(), 1, 98765)));
+        assert_eq!(parse_stat("1 (init"), None);
+        assert_eq!(parse_stat("1 ) init ("), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_proc_fs() {
+        let pid = std::process::id();
+        let list = ProcFsProcessList;
+        let current = list.get_process(pid).unwrap();
+
+        assert_eq!(current.exe_path, std::env::current_exe().ok().map(|p| p.to_string_lossy().into_owned()));
+        assert_eq!(current.cmdline, std::env::args().collect::<Vec<_>>());
+        assert!(current.start_time.unwrap() <= SystemTime::now());
+        assert!(list.process_list().iter().any(|p| p.pid == pid));
+        assert!(list.find_processes(&ProcessFilter::exact(current.name.clone())).iter().any(|p| p.pid == pid));
+    }
+}
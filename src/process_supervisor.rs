@@ -0,0 +1,269 @@
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use crate::*;
+
+/// An event emitted by a ProcessSupervisor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupervisorEvent {
+    /// A process was attached to
+    Attached { pid: u32 },
+    /// The attached process exited and was released
+    Detached { pid: u32 },
+}
+
+type AttachFn<P> = Box<dyn Fn() -> Option<P> + Send + Sync>;
+type LivenessFn<P> = Box<dyn Fn(&P) -> bool + Send + Sync>;
+
+/// Keeps a process attached across restarts. Every call to poll checks whether the current
+/// process is still alive, releases it if it exited, and attaches to a new instance when one appears.
+///
+/// Readers get the current handle from current(), or use the supervisor itself as a MemoryRead
+/// which always forwards to the latest process and fails while no process is attached.
+pub struct ProcessSupervisor<P> {
+    attach: AttachFn<P>,
+    is_alive: LivenessFn<P>,
+    current: RwLock<Option<Arc<P>>>,
+    subscribers: Mutex<Vec<Sender<SupervisorEvent>>>,
+    /// Held for a whole poll so concurrent polls cannot both attach or emit duplicate events
+    polling: Mutex<()>,
+}
+
+impl<P: ProcessInfo> ProcessSupervisor<P> {
+    /// Creates a supervisor that attaches with the attach function and checks if the process
+    /// is still alive with is_alive. Nothing is attached until poll is called
+    pub fn new(
+        attach: impl Fn() -> Option<P> + Send + Sync + 'static,
+        is_alive: impl Fn(&P) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            attach: Box::new(attach),
+            is_alive: Box::new(is_alive),
+            current: RwLock::new(None),
+            subscribers: Mutex::new(Vec::new()),
+            polling: Mutex::new(()),
+        }
+    }
+
+    /// Returns the attached process, or None if the process is not running
+    pub fn current(&self) -> Option<Arc<P>> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Returns a receiver for every event emitted after this call
+    pub fn subscribe(&self) -> Receiver<SupervisorEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    fn emit(&self, event: SupervisorEvent) {
+        self.subscribers.lock().unwrap().retain(|s| s.send(event).is_ok());
+    }
+
+    /// Detaches from the current process if it exited and attaches to a new process if none
+    /// is attached. Returns the attached process
+    pub fn poll(&self) -> Option<Arc<P>> {
+        let _polling = self.polling.lock().unwrap();
+        let current = self.current();
+        if let Some(process) = &current {
+            if (self.is_alive)(process) {
+                return current;
+            }
+            *self.current.write().unwrap() = None;
+            self.emit(SupervisorEvent::Detached { pid: process.pid() });
+        }
+
+        let process = Arc::new((self.attach)()?);
+        *self.current.write().unwrap() = Some(process.clone());
+        self.emit(SupervisorEvent::Attached { pid: process.pid() });
+        Some(process)
+    }
+
+    /// Polls on a background thread every interval. The thread exits once every other
+    /// reference to the supervisor is dropped
+    pub fn spawn(self: &Arc<Self>, interval: Duration) -> JoinHandle<()>
+        where P: Send + Sync + 'static {
+        let supervisor: Weak<Self> = Arc::downgrade(self);
+        std::thread::spawn(move || {
+            while let Some(supervisor) = supervisor.upgrade() {
+                supervisor.poll();
+                drop(supervisor);
+                std::thread::sleep(interval);
+            }
+        })
+    }
+}
+
+impl<T> ProcessSupervisor<AttachedProcess<'static, T>>
+    where T: GetContext + ProcessInfoPid + Clone + Send + Sync + 'static {
+    /// Creates a supervisor that attaches to a process of name process_name using a clone of api.
+    /// A process is considered alive as long as api can still attach to its pid
+    pub fn for_name(api: T, process_name: impl Into<String>) -> Self {
+        let process_name = process_name.into();
+        let liveness_api = api.clone();
+        Self::new(
+            move || api.clone().attach_into(&process_name),
+            move |process| liveness_api.get_context_from_pid(process.pid()).is_some(),
+        )
+    }
+}
+
+impl<P: ProcessInfo + MemoryRead> MemoryRead for ProcessSupervisor<P> {
+    fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        self.current()?.try_read_bytes_into(address, buffer)
+    }
+}
+
+impl<P: ProcessInfo + MemoryWrite> MemoryWrite for ProcessSupervisor<P> {
+    fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
+        self.current()?.try_write_bytes(address, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Instant;
+
+    /// A backend with at most one running process, identified by its pid
+    #[derive(Clone, Default)]
+    struct FakeApi {
+        running: Arc<AtomicU32>,
+    }
+
+    impl GetContext for FakeApi {
+        type Context = u32;
+
+        fn get_context_from_name(&self, process_name: &str) -> Option<u32> {
+            let pid = self.running.load(Ordering::SeqCst);
+            (process_name == "game.exe" && pid != 0).then_some(pid)
+        }
+
+        fn get_context_from_pid(&self, pid: u32) -> Option<u32> {
+            (pid != 0 && self.running.load(Ordering::SeqCst) == pid).then_some(pid)
+        }
+
+        fn get_current_context(&self) -> u32 {
+            0
+        }
+    }
+
+    impl ProcessInfoPid for FakeApi {
+        fn process_name(&self, _pid: &u32) -> String {
+            "game.exe".to_string()
+        }
+
+        fn peb_base_address(&self, _pid: &u32) -> u64 {
+            0
+        }
+
+        fn pid(&self, pid: &u32) -> u32 {
+            *pid
+        }
+    }
+
+    impl MemoryReadPid for FakeApi {
+        fn try_read_bytes_into_pid(&self, pid: &u32, _address: u64, buffer: &mut [u8]) -> Option<()> {
+            buffer.fill(*pid as u8);
+            Some(())
+        }
+    }
+
+    #[test]
+    fn test_attach_wait() {
+        let api = FakeApi::default();
+        let start = Instant::now();
+        assert!(api.attach_wait("game.exe", Duration::from_millis(150)).is_none());
+        assert!(start.elapsed() >= Duration::from_millis(150));
+
+        let running = api.running.clone();
+        let starter = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            running.store(7, Ordering::SeqCst);
+        });
+        assert_eq!(api.attach_wait("game.exe", Duration::from_secs(5)).unwrap().context, 7);
+        starter.join().unwrap();
+    }
+
+    #[test]
+    fn test_reattach() {
+        let api = FakeApi::default();
+        let supervisor = ProcessSupervisor::for_name(api.clone(), "game.exe");
+        let events = supervisor.subscribe();
+
+        assert!(supervisor.poll().is_none());
+        assert!(supervisor.try_read::<u8>(0).is_none());
+
+        api.running.store(10, Ordering::SeqCst);
+        let first = supervisor.poll().unwrap();
+        assert_eq!(supervisor.read::<u8>(0), 10);
+
+        // the game restarts with a new pid
+        api.running.store(11, Ordering::SeqCst);
+        supervisor.poll();
+        assert_eq!(supervisor.read::<u8>(0), 11);
+        assert_eq!(first.read::<u8>(0), 10, "old handles keep working until dropped");
+
+        api.running.store(0, Ordering::SeqCst);
+        assert!(supervisor.poll().is_none());
+        assert!(supervisor.current().is_none());
+
+        let events: Vec<_> = events.try_iter().collect();
+        assert_eq!(events, [
+            SupervisorEvent::Attached { pid: 10 },
+            SupervisorEvent::Detached { pid: 10 },
+            SupervisorEvent::Attached { pid: 11 },
+            SupervisorEvent::Detached { pid: 11 },
+        ]);
+    }
+
+    #[test]
+    fn test_concurrent_polls() {
+        let api = FakeApi::default();
+        let attaches = Arc::new(AtomicU32::new(0));
+        let supervisor = {
+            let (api, attaches) = (api.clone(), attaches.clone());
+            Arc::new(ProcessSupervisor::new(
+                move || {
+                    attaches.fetch_add(1, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(5));
+                    api.clone().attach_into("game.exe")
+                },
+                |_| true,
+            ))
+        };
+        let events = supervisor.subscribe();
+        api.running.store(4, Ordering::SeqCst);
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let supervisor = supervisor.clone();
+                std::thread::spawn(move || supervisor.poll().unwrap().pid())
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), 4);
+        }
+
+        assert_eq!(attaches.load(Ordering::SeqCst), 1);
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), [SupervisorEvent::Attached { pid: 4 }]);
+    }
+
+    #[test]
+    fn test_background_thread() {
+        let api = FakeApi::default();
+        let supervisor = Arc::new(ProcessSupervisor::for_name(api.clone(), "game.exe"));
+        let events = supervisor.subscribe();
+        let thread = supervisor.spawn(Duration::from_millis(5));
+
+        api.running.store(3, Ordering::SeqCst);
+        assert_eq!(events.recv_timeout(Duration::from_secs(5)), Ok(SupervisorEvent::Attached { pid: 3 }));
+        assert_eq!(supervisor.current().unwrap().pid(), 3);
+
+        drop(supervisor);
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use std::sync::{Arc, Mutex};
+use crate::*;
+
+/// Extension trait for supplying generic util methods for MemoryProtect
+pub trait MemoryProtectExt: MemoryProtect {
+    /// Changes the protection of range and returns a guard that restores the original protection
+    /// when it is dropped. The regions in the range are queried first so a range made of several
+    /// regions with different protections is restored region by region.
+    fn with_protection(&self, range: MemoryRange, protection: MemoryProtection) -> Result<ProtectionGuard<'_, Self>, MemoryProtectError>
+        where Self: MemoryRegions {
+        let mut original = Vec::new();
+        let mut address = range.start;
+        while address < range.end {
+            let region = self.query_region(address)
+                .filter(|r| r.range.end > address)
+                .ok_or_else(|| MemoryProtectError::InvalidMemoryRange(range.clone()))?;
+            let end = region.range.end.min(range.end);
+            original.push(MemoryRegion { range: address..end, protection: region.protection });
+            address = end;
+        }
+
+        self.set_protection(range, protection)?;
+        Ok(ProtectionGuard { api: self, original, log: None })
+    }
+}
+
+impl<T: MemoryProtect> MemoryProtectExt for T {}
+
+impl MemoryProtectExt for dyn MemoryProtect {}
+
+/// The result of restoring the protection of one region when a ProtectionGuard is dropped
+#[derive(Debug)]
+pub struct RestoreAttempt {
+    /// The range and the protection it was restored to
+    pub region: MemoryRegion,
+    /// The protection the range had before restoring, or the error returned by MemoryProtect
+    pub result: Result<MemoryProtection, MemoryProtectError>,
+}
+
+/// A shared list that a ProtectionGuard adds its restore attempts to, so they can be inspected
+/// after the guard is dropped
+pub type RestoreLog = Arc<Mutex<Vec<RestoreAttempt>>>;
+
+/// Restores the original protection of a range of memory when dropped, including when
+/// unwinding from a panic. Created with MemoryProtectExt::with_protection
+pub struct ProtectionGuard<'a, P: MemoryProtect + ?Sized> {
+    api: &'a P,
+    original: Vec<MemoryRegion>,
+    log: Option<RestoreLog>,
+}
+
+impl<'a, P: MemoryProtect + ?Sized> ProtectionGuard<'a, P> {
+    /// Returns the original protection of every region in the range
+    pub fn original(&self) -> &[MemoryRegion] {
+        &self.original
+    }
+
+    /// Adds the restore attempts made when the guard is dropped to log
+    pub fn log_to(mut self, log: RestoreLog) -> Self {
+        self.log = Some(log);
+        self
+    }
+
+    /// Restores the original protection now and returns every attempt
+    pub fn restore(mut self) -> Vec<RestoreAttempt> {
+        self.restore_all()
+    }
+
+    /// Keeps the new protection instead of restoring the original
+    pub fn forget(mut self) {
+        self.original.clear();
+    }
+
+    fn restore_all(&mut self) -> Vec<RestoreAttempt> {
+        core::mem::take(&mut self.original).into_iter()
+            .rev()
+            .map(|region| {
+                let result = self.api.set_protection(region.range.clone(), region.protection);
+                RestoreAttempt { region, result }
+            })
+            .collect()
+    }
+}
+
+impl<'a, P: MemoryProtect + ?Sized> Drop for ProtectionGuard<'a, P> {
+    fn drop(&mut self) {
+        let attempts = self.restore_all();
+        if let Some(log) = &self.log {
+            // a poisoned log still collects attempts, since the guard may be dropped while unwinding
+            log.lock().unwrap_or_else(|e| e.into_inner()).extend(attempts);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process() -> MockProcess {
+        let process = MockProcess::new();
+        process.add_region(0x1000, vec![0; 0x1000], MemoryProtection::READONLY);
+        process.add_region(0x2000, vec![0; 0x1000], MemoryProtection::EXECUTE_READ);
+        process.add_region(0x3000, vec![0; 0x1000], MemoryProtection::READWRITE);
+        process
+    }
+
+    fn protections(process: &MockProcess) -> Vec<(MemoryRange, MemoryProtection)> {
+        process.regions().iter().map(|r| (r.memory_range(), r.protection)).collect()
+    }
+
+    #[test]
+    fn test_restores_every_region() {
+        let process = process();
+        let before = protections(&process);
+        {
+            let guard = process.with_protection(0x1000..0x4000, MemoryProtection::EXECUTE_READWRITE).unwrap();
+            assert_eq!(guard.original().len(), 3);
+            process.write(0x1FFE, &0xFFFFu32);
+        }
+        assert_eq!(protections(&process), before);
+        assert_eq!(process.read::<u32>(0x1FFE), 0xFFFF);
+    }
+
+    #[test]
+    fn test_restores_on_panic() {
+        let process = process();
+        let log = RestoreLog::default();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = process.with_protection(0x2000..0x3000, MemoryProtection::READWRITE).unwrap().log_to(log.clone());
+            panic!("early exit");
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(process.region_at(0x2000).unwrap().protection, MemoryProtection::EXECUTE_READ);
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].result.as_ref().unwrap(), &MemoryProtection::READWRITE);
+    }
+
+    #[test]
+    fn test_failed_restore_is_recorded() {
+        let process = process();
+        let guard = process.with_protection(0x2000..0x4000, MemoryProtection::READWRITE).unwrap();
+        process.free(0x3000, 0).unwrap();
+
+        let attempts = guard.restore();
+        assert_eq!(attempts.len(), 2);
+        assert!(matches!(attempts[0].result, Err(MemoryProtectError::InvalidMemoryRange(_))));
+        assert!(attempts[1].result.is_ok());
+        assert_eq!(process.region_at(0x2000).unwrap().protection, MemoryProtection::EXECUTE_READ);
+    }
+
+    #[test]
+    fn test_unmapped_range() {
+        let process = process();
+        assert!(process.with_protection(0x3000..0x5000, MemoryProtection::READWRITE).is_err());
+        assert_eq!(process.region_at(0x3000).unwrap().protection, MemoryProtection::READWRITE);
+    }
+}
@@ -0,0 +1,605 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::*;
+
+const RECORDING_MAGIC: &[u8; 4] = b"MLRC";
+const RECORDING_VERSION: u8 = 1;
+
+/// A single call made through a Recorder along with its result
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordedCall {
+    /// A read of len bytes at address. data is None if the read failed
+    Read { address: u64, len: usize, data: Option<Vec<u8>> },
+    ModuleList(Vec<Module>),
+    MainModule(Module),
+    ProcessName(String),
+    PebBaseAddress(u64),
+    Pid(u32),
+}
+
+/// A recorded call and the time it was made relative to the start of the recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedEvent {
+    pub timestamp: Duration,
+    pub call: RecordedCall,
+}
+
+/// A list of calls recorded by a Recorder that can be saved to a file and replayed with a Replayer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    pub events: Vec<RecordedEvent>,
+}
+
+impl Recording {
+    /// Loads a recording from a file created with Recording::save
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read_from(io::BufReader::new(std::fs::File::open(path)?))
+    }
+
+    /// Saves the recording to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Serializes the recording. Integers are stored as LEB128 varints to keep the file small
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        let w = &mut writer;
+        w.write_all(RECORDING_MAGIC)?;
+        w.write_all(&[RECORDING_VERSION])?;
+        write_varint(w, self.events.len() as u64)?;
+
+        for event in &self.events {
+            let tag = match &event.call {
+                RecordedCall::Read { data: Some(_), .. } => 0,
+                RecordedCall::Read { data: None, .. } => 1,
+                RecordedCall::ModuleList(_) => 2,
+                RecordedCall::MainModule(_) => 3,
+                RecordedCall::ProcessName(_) => 4,
+                RecordedCall::PebBaseAddress(_) => 5,
+                RecordedCall::Pid(_) => 6,
+            };
+            w.write_all(&[tag])?;
+            write_varint(w, event.timestamp.as_micros() as u64)?;
+
+            match &event.call {
+                RecordedCall::Read { address, len, data } => {
+                    write_varint(w, *address)?;
+                    write_varint(w, *len as u64)?;
+                    if let Some(data) = data {
+                        w.write_all(data)?;
+                    }
+                }
+                RecordedCall::ModuleList(modules) => {
+                    write_varint(w, modules.len() as u64)?;
+                    for module in modules {
+                        write_module(w, module)?;
+                    }
+                }
+                RecordedCall::MainModule(module) => write_module(w, module)?,
+                RecordedCall::ProcessName(name) => write_bytes(w, name.as_bytes())?,
+                RecordedCall::PebBaseAddress(peb) => write_varint(w, *peb)?,
+                RecordedCall::Pid(pid) => write_varint(w, *pid as u64)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deserializes a recording created with Recording::write_to
+    pub fn read_from(mut reader: impl Read) -> io::Result<Self> {
+        let r = &mut reader;
+        let mut header = [0u8; 5];
+        r.read_exact(&mut header)?;
+        if &header[..4] != RECORDING_MAGIC {
+            return Err(invalid_data("not a memlib recording"));
+        }
+        if header[4] != RECORDING_VERSION {
+            return Err(invalid_data(format!("unsupported recording version {}", header[4])));
+        }
+
+        let count = read_varint(r)?;
+        let mut events = Vec::new();
+        for _ in 0..count {
+            let mut tag = [0u8];
+            r.read_exact(&mut tag)?;
+            let timestamp = Duration::from_micros(read_varint(r)?);
+
+            let call = match tag[0] {
+                tag @ (0 | 1) => {
+                    let address = read_varint(r)?;
+                    let len = read_varint(r)? as usize;
+                    let data = if tag == 0 {
+                        Some(read_exact_len(r, len as u64)?)
+                    } else {
+                        None
+                    };
+                    RecordedCall::Read { address, len, data }
+                }
+                2 => {
+                    let len = read_varint(r)?;
+                    RecordedCall::ModuleList((0..len).map(|_| read_module(r)).collect::<io::Result<_>>()?)
+                }
+                3 => RecordedCall::MainModule(read_module(r)?),
+                4 => RecordedCall::ProcessName(read_string(r)?),
+                5 => RecordedCall::PebBaseAddress(read_varint(r)?),
+                6 => RecordedCall::Pid(read_varint(r)? as u32),
+                tag => return Err(invalid_data(format!("unknown event tag {}", tag))),
+            };
+            events.push(RecordedEvent { timestamp, call });
+        }
+
+        Ok(Self { events })
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn write_varint(w: &mut impl Write, mut n: u64) -> io::Result<()> {
+    loop {
+        let byte = (n & 0x7F) as u8;
+        n >>= 7;
+        if n == 0 {
+            return w.write_all(&[byte]);
+        }
+        w.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint(r: &mut impl Read) -> io::Result<u64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8];
+        r.read_exact(&mut byte)?;
+        n |= ((byte[0] & 0x7F) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(invalid_data("varint is too long"))
+}
+
+fn write_bytes(w: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    write_varint(w, bytes.len() as u64)?;
+    w.write_all(bytes)
+}
+
+/// Reads len bytes without allocating them up front, so a corrupt length fails at the end of
+/// the data instead of allocating a huge buffer
+fn read_exact_len(r: &mut impl Read, len: u64) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    r.by_ref().take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(invalid_data(format!("expected {} bytes but the recording ends after {}", len, bytes.len())));
+    }
+    Ok(bytes)
+}
+
+fn read_string(r: &mut impl Read) -> io::Result<String> {
+    let len = read_varint(r)?;
+    let bytes = read_exact_len(r, len)?;
+    String::from_utf8(bytes).map_err(|e| invalid_data(e.to_string()))
+}
+
+fn write_module(w: &mut impl Write, module: &Module) -> io::Result<()> {
+    write_bytes(w, module.name.as_bytes())?;
+    write_varint(w, module.base)?;
+    write_varint(w, module.size)
+}
+
+fn read_module(r: &mut impl Read) -> io::Result<Module> {
+    Ok(Module {
+        name: read_string(r)?,
+        base: read_varint(r)?,
+        size: read_varint(r)?,
+    })
+}
+
+/// Wraps a MemoryRead, ModuleList or ProcessInfo implementation and records every read and
+/// query along with its result. The recording can be saved and replayed with a Replayer to
+/// reproduce a session without the original target. Writes are passed through and not recorded.
+pub struct Recorder<M> {
+    inner: M,
+    start: Instant,
+    events: Mutex<Vec<RecordedEvent>>,
+}
+
+impl<M> Recorder<M> {
+    pub fn new(inner: M) -> Self {
+        Self { inner, start: Instant::now(), events: Mutex::new(Vec::new()) }
+    }
+
+    /// Returns a copy of everything recorded so far
+    pub fn recording(&self) -> Recording {
+        Recording { events: self.events.lock().unwrap().clone() }
+    }
+
+    /// Saves everything recorded so far to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.recording().save(path)
+    }
+
+    /// Consumes the recorder and returns the inner type and the recording
+    pub fn into_inner(self) -> (M, Recording) {
+        (self.inner, Recording { events: self.events.into_inner().unwrap() })
+    }
+
+    fn record(&self, call: RecordedCall) {
+        // recordings store timestamps with microsecond precision
+        let timestamp = Duration::from_micros(self.start.elapsed().as_micros() as u64);
+        self.events.lock().unwrap().push(RecordedEvent { timestamp, call });
+    }
+}
+
+impl<M: MemoryRead> MemoryRead for Recorder<M> {
+    fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        let result = self.inner.try_read_bytes_into(address, buffer);
+        self.record(RecordedCall::Read {
+            address,
+            len: buffer.len(),
+            data: result.map(|_| buffer.to_vec()),
+        });
+        result
+    }
+}
+
+impl<M: MemoryWrite> MemoryWrite for Recorder<M> {
+    fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
+        self.inner.try_write_bytes(address, buffer)
+    }
+}
+
+impl<M: ModuleList> ModuleList for Recorder<M> {
+    fn get_module_list(&self) -> Vec<Module> {
+        let modules = self.inner.get_module_list();
+        self.record(RecordedCall::ModuleList(modules.clone()));
+        modules
+    }
+
+    fn get_main_module(&self) -> Module {
+        let module = self.inner.get_main_module();
+        self.record(RecordedCall::MainModule(module.clone()));
+        module
+    }
+}
+
+impl<M: ProcessInfo> ProcessInfo for Recorder<M> {
+    fn process_name(&self) -> String {
+        let name = self.inner.process_name();
+        self.record(RecordedCall::ProcessName(name.clone()));
+        name
+    }
+
+    fn peb_base_address(&self) -> u64 {
+        let peb = self.inner.peb_base_address();
+        self.record(RecordedCall::PebBaseAddress(peb));
+        peb
+    }
+
+    fn pid(&self) -> u32 {
+        let pid = self.inner.pid();
+        self.record(RecordedCall::Pid(pid));
+        pid
+    }
+}
+
+/// How a Replayer handles requests that were not recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayMode {
+    /// Every request must match a recorded request exactly. Unknown reads fail and are
+    /// listed by Replayer::unmatched_reads
+    Strict,
+    /// Reads that were not recorded exactly are served from the bytes of overlapping
+    /// recorded reads, and fail if any byte was never read
+    Lenient,
+}
+
+#[derive(Hash, PartialEq, Eq)]
+enum QueryKind {
+    ModuleList,
+    MainModule,
+    ProcessName,
+    PebBaseAddress,
+    Pid,
+}
+
+impl QueryKind {
+    /// Returns the name of the method that makes the query
+    fn method(&self) -> &'static str {
+        match self {
+            Self::ModuleList => "get_module_list",
+            Self::MainModule => "get_main_module",
+            Self::ProcessName => "process_name",
+            Self::PebBaseAddress => "peb_base_address",
+            Self::Pid => "pid",
+        }
+    }
+}
+
+/// Serves recorded responses in order. Once every response has been served the last one repeats
+struct ResponseQueue<T> {
+    responses: Vec<T>,
+    next: usize,
+}
+
+impl<T: Clone> ResponseQueue<T> {
+    fn pop(&mut self) -> T {
+        let response = self.responses[self.next.min(self.responses.len() - 1)].clone();
+        self.next += 1;
+        response
+    }
+}
+
+type ReadQueue = ResponseQueue<Option<Vec<u8>>>;
+
+/// Implements MemoryRead, ModuleList and ProcessInfo by serving the responses from a Recording.
+/// Repeated identical requests are answered in the order they were recorded. Queries that were
+/// never recorded return an empty or zero value in both modes and are listed by
+/// Replayer::unmatched_queries.
+pub struct Replayer {
+    mode: ReplayMode,
+    reads: Mutex<HashMap<(u64, usize), ReadQueue>>,
+    queries: Mutex<HashMap<QueryKind, ResponseQueue<RecordedCall>>>,
+    /// Successful reads in the order they were recorded, used to serve lenient reads
+    read_data: Vec<(u64, Vec<u8>)>,
+    unmatched: Mutex<Vec<(u64, usize)>>,
+    unmatched_queries: Mutex<Vec<&'static str>>,
+}
+
+impl Replayer {
+    pub fn new(recording: Recording, mode: ReplayMode) -> Self {
+        let mut reads: HashMap<_, ResponseQueue<_>> = HashMap::new();
+        let mut queries: HashMap<_, ResponseQueue<_>> = HashMap::new();
+        let mut read_data = Vec::new();
+
+        for event in recording.events {
+            let kind = match event.call {
+                RecordedCall::Read { address, len, data } => {
+                    if let Some(data) = &data {
+                        read_data.push((address, data.clone()));
+                    }
+                    reads.entry((address, len))
+                        .or_insert_with(|| ResponseQueue { responses: Vec::new(), next: 0 })
+                        .responses.push(data);
+                    continue;
+                }
+                RecordedCall::ModuleList(_) => QueryKind::ModuleList,
+                RecordedCall::MainModule(_) => QueryKind::MainModule,
+                RecordedCall::ProcessName(_) => QueryKind::ProcessName,
+                RecordedCall::PebBaseAddress(_) => QueryKind::PebBaseAddress,
+                RecordedCall::Pid(_) => QueryKind::Pid,
+            };
+            queries.entry(kind)
+                .or_insert_with(|| ResponseQueue { responses: Vec::new(), next: 0 })
+                .responses.push(event.call);
+        }
+
+        Self {
+            mode,
+            reads: Mutex::new(reads),
+            queries: Mutex::new(queries),
+            read_data,
+            unmatched: Mutex::new(Vec::new()),
+            unmatched_queries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the address and length of every read that failed in strict mode because it
+    /// was never recorded
+    pub fn unmatched_reads(&self) -> Vec<(u64, usize)> {
+        self.unmatched.lock().unwrap().clone()
+    }
+
+    /// Returns the name of the method of every query that returned a fallback value because
+    /// it was never recorded
+    pub fn unmatched_queries(&self) -> Vec<&'static str> {
+        self.unmatched_queries.lock().unwrap().clone()
+    }
+
+    /// Loads a recording from a file and creates a replayer for it
+    pub fn load(path: impl AsRef<Path>, mode: ReplayMode) -> io::Result<Self> {
+        Ok(Self::new(Recording::load(path)?, mode))
+    }
+
+    /// Returns the next recorded response of the query, or None after noting it as unmatched
+    fn query(&self, kind: QueryKind) -> Option<RecordedCall> {
+        let response = self.queries.lock().unwrap().get_mut(&kind).map(|queue| queue.pop());
+        if response.is_none() {
+            self.unmatched_queries.lock().unwrap().push(kind.method());
+        }
+        response
+    }
+
+    /// Fills buffer from the most recent recorded reads that overlap it
+    fn read_overlapping(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        let end = address.checked_add(buffer.len() as u64)?;
+        let mut filled = vec![false; buffer.len()];
+        let mut remaining = buffer.len();
+
+        for (read_address, data) in self.read_data.iter().rev() {
+            // recordings are loaded from files, so a read can claim to end past the address space
+            let Some(read_end) = read_address.checked_add(data.len() as u64) else { continue };
+            let start = address.max(*read_address);
+            let stop = end.min(read_end);
+            for addr in start..stop {
+                let i = (addr - address) as usize;
+                if !filled[i] {
+                    buffer[i] = data[(addr - read_address) as usize];
+                    filled[i] = true;
+                    remaining -= 1;
+                }
+            }
+            if remaining == 0 {
+                return Some(());
+            }
+        }
+
+        None
+    }
+}
+
+impl MemoryRead for Replayer {
+    fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        let recorded = self.reads.lock().unwrap()
+            .get_mut(&(address, buffer.len()))
+            .map(|queue| queue.pop());
+
+        match (recorded, self.mode) {
+            (Some(Some(data)), _) => {
+                buffer.copy_from_slice(&data);
+                Some(())
+            }
+            (Some(None), _) => None,
+            (None, ReplayMode::Lenient) => self.read_overlapping(address, buffer),
+            (None, ReplayMode::Strict) => {
+                self.unmatched.lock().unwrap().push((address, buffer.len()));
+                None
+            }
+        }
+    }
+}
+
+impl ModuleList for Replayer {
+    fn get_module_list(&self) -> Vec<Module> {
+        match self.query(QueryKind::ModuleList) {
+            Some(RecordedCall::ModuleList(modules)) => modules,
+            _ => Vec::new(),
+        }
+    }
+
+    fn get_main_module(&self) -> Module {
+        match self.query(QueryKind::MainModule) {
+            Some(RecordedCall::MainModule(module)) => module,
+            _ => Module { name: String::new(), base: 0, size: 0 },
+        }
+    }
+}
+
+impl ProcessInfo for Replayer {
+    fn process_name(&self) -> String {
+        match self.query(QueryKind::ProcessName) {
+            Some(RecordedCall::ProcessName(name)) => name,
+            _ => String::new(),
+        }
+    }
+
+    fn peb_base_address(&self) -> u64 {
+        match self.query(QueryKind::PebBaseAddress) {
+            Some(RecordedCall::PebBaseAddress(peb)) => peb,
+            _ => 0,
+        }
+    }
+
+    fn pid(&self) -> u32 {
+        match self.query(QueryKind::Pid) {
+            Some(RecordedCall::Pid(pid)) => pid,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_session() -> Recording {
+        let process = MockProcess::new().with_process_name("game.exe").with_pid(77);
+        process.add_module("game.exe", 0x1000, (0..=255).collect(), MemoryProtection::READWRITE);
+
+        let recorder = Recorder::new(&process);
+        assert_eq!(recorder.process_name(), "game.exe");
+        assert_eq!(recorder.pid(), 77);
+        let module = recorder.get_module("game.exe").unwrap();
+        assert_eq!(recorder.read::<u32>(module.base + 4), 0x07060504);
+        process.write(0x1004, &0u32);
+        assert_eq!(recorder.read::<u32>(module.base + 4), 0);
+        assert!(recorder.try_read::<u8>(0x5000).is_none());
+        recorder.recording()
+    }
+
+    #[test]
+    fn test_serialize_roundtrip() {
+        let recording = record_session();
+        let mut bytes = Vec::new();
+        recording.write_to(&mut bytes).unwrap();
+
+        assert_eq!(Recording::read_from(&bytes[..]).unwrap(), recording);
+        assert!(Recording::read_from(&bytes[1..]).is_err());
+
+        // a huge length from a corrupt file fails instead of allocating it
+        let mut corrupt = b"MLRC\x01\x01\x00\x00\x00".to_vec();
+        corrupt.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 1, 2]);
+        let error = Recording::read_from(&corrupt[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_replay_strict() {
+        let replayer = Replayer::new(record_session(), ReplayMode::Strict);
+
+        assert_eq!(replayer.process_name(), "game.exe");
+        assert_eq!(replayer.pid(), 77);
+        assert_eq!(replayer.get_module("GAME.exe").unwrap().base, 0x1000);
+        assert_eq!(replayer.read::<u32>(0x1004), 0x07060504);
+        assert_eq!(replayer.read::<u32>(0x1004), 0);
+        assert_eq!(replayer.read::<u32>(0x1004), 0);
+        assert!(replayer.try_read::<u8>(0x5000).is_none());
+    }
+
+    #[test]
+    fn test_replay_strict_unknown_read() {
+        let replayer = Replayer::new(record_session(), ReplayMode::Strict);
+        assert!(replayer.try_read::<u16>(0x1004).is_none());
+        assert_eq!(replayer.unmatched_reads(), [(0x1004, 2)]);
+    }
+
+    #[test]
+    fn test_replay_unknown_queries() {
+        for mode in [ReplayMode::Strict, ReplayMode::Lenient] {
+            let replayer = Replayer::new(Recording::default(), mode);
+            assert!(replayer.get_module_list().is_empty());
+            assert!(replayer.get_module("game.exe").is_none());
+            assert_eq!(replayer.get_main_module().base, 0);
+            assert_eq!(replayer.process_name(), "");
+            assert_eq!(replayer.peb_base_address(), 0);
+            assert_eq!(replayer.pid(), 0);
+            assert_eq!(
+                replayer.unmatched_queries(),
+                ["get_module_list", "get_module_list", "get_main_module", "process_name", "peb_base_address", "pid"]
+            );
+        }
+
+        // recorded queries are still answered
+        let replayer = Replayer::new(record_session(), ReplayMode::Strict);
+        assert_eq!(replayer.pid(), 77);
+        assert!(replayer.unmatched_queries().is_empty());
+    }
+
+    #[test]
+    fn test_replay_lenient() {
+        let replayer = Replayer::new(record_session(), ReplayMode::Lenient);
+
+        // served from the most recent overlapping read
+        assert_eq!(replayer.try_read_bytes(0x1005, 2), Some(vec![0, 0]));
+        assert_eq!(replayer.try_read_bytes(0x1003, 2), None);
+        assert_eq!(replayer.try_read_bytes(0x1004, 4), Some(vec![4, 5, 6, 7]));
+    }
+
+    #[test]
+    fn test_replay_lenient_overflowing_read() {
+        let mut recording = record_session();
+        recording.events.push(RecordedEvent {
+            timestamp: Duration::ZERO,
+            call: RecordedCall::Read { address: u64::MAX - 1, len: 4, data: Some(vec![1, 2, 3, 4]) },
+        });
+        let replayer = Replayer::new(recording, ReplayMode::Lenient);
+        assert_eq!(replayer.try_read_bytes(0x1005, 2), Some(vec![0, 0]));
+        assert_eq!(replayer.try_read_bytes(u64::MAX - 1, 1), None);
+    }
+}
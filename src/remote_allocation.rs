@@ -0,0 +1,155 @@
+use crate::*;
+
+/// Extension trait for supplying generic util methods for MemoryAllocate
+pub trait MemoryAllocateExt: MemoryAllocate {
+    /// Allocates size bytes of memory in the process with the specified protection and returns
+    /// a RemoteAllocation that frees the memory when it is dropped
+    fn allocate_guarded(&self, size: u64, protection: MemoryProtection) -> Result<RemoteAllocation<'_, Self>, MemoryAllocateError> {
+        let base = self.allocate(size, protection)?;
+        Ok(RemoteAllocation { api: self, base, size, protection })
+    }
+}
+
+impl<T: MemoryAllocate> MemoryAllocateExt for T {}
+
+impl MemoryAllocateExt for dyn MemoryAllocate {}
+
+/// A block of memory allocated in a process with MemoryAllocate that is freed when dropped.
+/// Reading and writing a RemoteAllocation uses offsets from the base of the allocation and
+/// any access outside of the allocation is rejected.
+pub struct RemoteAllocation<'a, A: MemoryAllocate + ?Sized> {
+    api: &'a A,
+    base: u64,
+    size: u64,
+    protection: MemoryProtection,
+}
+
+impl<'a, A: MemoryAllocate + ?Sized> RemoteAllocation<'a, A> {
+    /// Returns the address of the allocation in the process
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    /// Returns the size that was requested when allocating
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the current protection of the allocation
+    pub fn protection(&self) -> MemoryProtection {
+        self.protection
+    }
+
+    /// Returns the memory range of the allocation in the process
+    pub fn memory_range(&self) -> MemoryRange {
+        self.base..(self.base + self.size)
+    }
+
+    /// Returns the process address of an offset into the allocation
+    pub fn address_of(&self, offset: u64) -> u64 {
+        self.base + offset
+    }
+
+    /// Keeps the memory allocated and returns its base address
+    pub fn leak(self) -> u64 {
+        let base = self.base;
+        core::mem::forget(self);
+        base
+    }
+
+    /// Frees the memory and returns any error from MemoryAllocate::free
+    pub fn free(self) -> Result<(), MemoryAllocateError> {
+        let (api, base, size) = (self.api, self.base, self.size);
+        core::mem::forget(self);
+        api.free(base, size)
+    }
+
+    /// Changes the protection of the whole allocation and returns the old protection
+    pub fn set_protection(&mut self, protection: MemoryProtection) -> Result<MemoryProtection, MemoryProtectError>
+        where A: MemoryProtect {
+        let old = self.api.set_protection(self.memory_range(), protection)?;
+        self.protection = protection;
+        Ok(old)
+    }
+
+    /// Returns the process address of an access of len bytes at offset,
+    /// or None if it does not fit in the allocation
+    fn translate(&self, offset: u64, len: usize) -> Option<u64> {
+        if offset.checked_add(len as u64)? > self.size {
+            return None;
+        }
+        Some(self.base + offset)
+    }
+}
+
+impl<'a, A: MemoryAllocate + MemoryRead + ?Sized> MemoryRead for RemoteAllocation<'a, A> {
+    fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        let address = self.translate(address, buffer.len())?;
+        self.api.try_read_bytes_into(address, buffer)
+    }
+}
+
+impl<'a, A: MemoryAllocate + MemoryWrite + ?Sized> MemoryWrite for RemoteAllocation<'a, A> {
+    fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
+        let address = self.translate(address, buffer.len())?;
+        self.api.try_write_bytes(address, buffer)
+    }
+}
+
+impl<'a, A: MemoryAllocate + ?Sized> Drop for RemoteAllocation<'a, A> {
+    fn drop(&mut self) {
+        let _ = self.api.free(self.base, self.size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_freed_on_drop() {
+        let process = MockProcess::new();
+        let base = {
+            let allocation = process.allocate_guarded(0x20, MemoryProtection::READWRITE).unwrap();
+            allocation.write(0x10, &0x1234u32);
+            assert_eq!(process.read::<u32>(allocation.address_of(0x10)), 0x1234);
+            assert!(allocation.try_read::<u32>(0x1E).is_none());
+            allocation.base()
+        };
+        assert!(process.region_at(base).is_none());
+    }
+
+    #[test]
+    fn test_freed_on_early_return() {
+        fn inject(process: &MockProcess) -> Option<u64> {
+            let allocation = process.allocate_guarded(0x10, MemoryProtection::READONLY).ok()?;
+            allocation.try_write(0, &1u8)?;
+            Some(allocation.leak())
+        }
+
+        let process = MockProcess::new();
+        assert!(inject(&process).is_none());
+        assert!(process.regions().is_empty());
+    }
+
+    #[test]
+    fn test_leak() {
+        let process = MockProcess::new();
+        let base = process.allocate_guarded(0x10, MemoryProtection::READWRITE).unwrap().leak();
+        assert!(process.region_at(base).is_some());
+    }
+
+    #[test]
+    fn test_set_protection() {
+        let process = MockProcess::new();
+        let mut allocation = process.allocate_guarded(0x10, MemoryProtection::READWRITE).unwrap();
+        allocation.write(0, &0xC3u8);
+
+        let old = allocation.set_protection(MemoryProtection::EXECUTE_READ).unwrap();
+        assert_eq!(old, MemoryProtection::READWRITE);
+        assert_eq!(allocation.protection(), MemoryProtection::EXECUTE_READ);
+        assert!(allocation.try_write(0, &0u8).is_none());
+        assert_eq!(allocation.read::<u8>(0), 0xC3);
+        allocation.free().unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+use crate::*;
+
+/// A bump allocator for many small objects in a remote process. Memory is reserved with
+/// MemoryAllocate in large blocks and handed out in aligned chunks, so injecting dozens of
+/// strings or structs costs a few allocations instead of a page each. Every block is freed
+/// when the arena is dropped.
+pub struct RemoteArena<'a, A: MemoryAllocate + MemoryWrite + ?Sized> {
+    api: &'a A,
+    block_size: u64,
+    protection: MemoryProtection,
+    blocks: Vec<MemoryRange>,
+    /// The address of the next free byte in the current block
+    cursor: u64,
+    /// The end of the current block
+    limit: u64,
+}
+
+impl<'a, A: MemoryAllocate + MemoryWrite + ?Sized> RemoteArena<'a, A> {
+    /// Creates an arena that reserves blocks of block_size bytes with the specified protection.
+    /// No memory is allocated until the first push
+    pub fn new(api: &'a A, block_size: u64, protection: MemoryProtection) -> Self {
+        assert_ne!(block_size, 0, "block_size must be greater than zero");
+        Self { api, block_size, protection, blocks: Vec::new(), cursor: 0, limit: 0 }
+    }
+
+    /// Reserves size bytes aligned to align and returns the address. The memory is not initialized.
+    /// Requests larger than the block size get a block of their own
+    pub fn alloc(&mut self, size: u64, align: u64) -> Result<u64, MemoryAllocateError> {
+        assert!(align.is_power_of_two(), "align must be a power of two");
+
+        if let Some(address) = self.bump(size, align) {
+            return Ok(address);
+        }
+
+        // blocks are at least page aligned, so the start of a new block satisfies any
+        // alignment up to the page size. Reserve extra space for larger alignments.
+        let needed = size.max(1) + align.saturating_sub(0x1000);
+        let block_size = self.block_size.max(needed);
+        let base = self.api.allocate(block_size, self.protection)?;
+        self.blocks.push(base..base + block_size);
+        self.cursor = base;
+        self.limit = base + block_size;
+
+        self.bump(size, align)
+            .ok_or_else(|| MemoryAllocateError::Message(format!("block at {:#X} is not aligned to {:#X}", base, align)))
+    }
+
+    fn bump(&mut self, size: u64, align: u64) -> Option<u64> {
+        if self.blocks.is_empty() {
+            return None;
+        }
+        let address = self.cursor.checked_add(align - 1)? & !(align - 1);
+        let end = address.checked_add(size)?;
+        if end > self.limit {
+            return None;
+        }
+        self.cursor = end;
+        Some(address)
+    }
+
+    /// Writes bytes into the arena aligned to align and returns their address
+    pub fn push_bytes_aligned(&mut self, bytes: &[u8], align: u64) -> Result<u64, MemoryAllocateError> {
+        let address = self.alloc(bytes.len() as u64, align)?;
+        self.api.try_write_bytes(address, bytes)
+            .ok_or_else(|| MemoryAllocateError::Message(format!("failed to write {:#X} bytes at {:#X}", bytes.len(), address)))?;
+        Ok(address)
+    }
+
+    /// Writes bytes into the arena and returns their address
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> Result<u64, MemoryAllocateError> {
+        self.push_bytes_aligned(bytes, 1)
+    }
+
+    /// Writes a value into the arena aligned for its type and returns its address
+    pub fn push<T: Pod>(&mut self, value: &T) -> Result<u64, MemoryAllocateError> {
+        self.push_bytes_aligned(value.as_bytes(), core::mem::align_of::<T>() as u64)
+    }
+
+    /// Writes a null terminated string into the arena and returns its address
+    pub fn push_str(&mut self, s: &str) -> Result<u64, MemoryAllocateError> {
+        let mut bytes = Vec::with_capacity(s.len() + 1);
+        bytes.extend_from_slice(s.as_bytes());
+        bytes.push(0);
+        self.push_bytes(&bytes)
+    }
+
+    /// Writes a null terminated UTF-16 string into the arena and returns its address
+    pub fn push_str_wide(&mut self, s: &str) -> Result<u64, MemoryAllocateError> {
+        let bytes: Vec<u8> = s.encode_utf16().chain(Some(0)).flat_map(u16::to_le_bytes).collect();
+        self.push_bytes_aligned(&bytes, 2)
+    }
+
+    /// Returns the memory range of every block reserved by the arena
+    pub fn blocks(&self) -> &[MemoryRange] {
+        &self.blocks
+    }
+
+    /// Keeps every block allocated and returns their memory ranges
+    pub fn leak(mut self) -> Vec<MemoryRange> {
+        core::mem::take(&mut self.blocks)
+    }
+}
+
+impl<'a, A: MemoryAllocate + MemoryWrite + ?Sized> Drop for RemoteArena<'a, A> {
+    fn drop(&mut self) {
+        for block in &self.blocks {
+            let _ = self.api.free(block.start, block.end - block.start);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push() {
+        let process = MockProcess::new();
+        let mut arena = RemoteArena::new(&process, 0x1000, MemoryProtection::READWRITE);
+
+        let s = arena.push_str("kernel32.dll").unwrap();
+        let n = arena.push(&0x1122334455667788u64).unwrap();
+        let w = arena.push_str_wide("hi").unwrap();
+
+        assert_eq!(process.try_read_string(s).unwrap().unwrap(), "kernel32.dll");
+        assert_eq!(n % 8, 0);
+        assert_eq!(process.read::<u64>(n), 0x1122334455667788);
+        assert_eq!(w % 2, 0);
+        assert_eq!(process.try_read_string_wide(w).unwrap().unwrap(), "hi");
+        assert_eq!(arena.blocks().len(), 1);
+    }
+
+    #[test]
+    fn test_never_overruns_block() {
+        let process = MockProcess::new();
+        let mut arena = RemoteArena::new(&process, 0x1000, MemoryProtection::READWRITE);
+
+        let mut allocations = Vec::new();
+        let mut seed = 0x2545F4914F6CDD1Du64;
+        for i in 0..500u64 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let size = seed % 0x180 + 1;
+            let align = 1 << (seed % 5);
+            let data = vec![i as u8; size as usize];
+            let address = arena.push_bytes_aligned(&data, align).unwrap();
+            assert_eq!(address % align, 0);
+            allocations.push((address..address + size, data));
+        }
+
+        let blocks = arena.blocks().to_vec();
+        for (range, data) in &allocations {
+            assert!(blocks.iter().any(|b| b.start <= range.start && range.end <= b.end), "{:X?} overruns its block", range);
+            assert_eq!(&process.try_read_bytes(range.start, data.len()).unwrap(), data);
+        }
+        allocations.sort_by_key(|(r, _)| r.start);
+        assert!(allocations.windows(2).all(|w| w[0].0.end <= w[1].0.start));
+        assert_eq!(process.regions().len(), blocks.len());
+
+        drop(arena);
+        assert!(process.regions().is_empty());
+    }
+
+    #[test]
+    fn test_oversized_push() {
+        let process = MockProcess::new();
+        let mut arena = RemoteArena::new(&process, 0x1000, MemoryProtection::READWRITE);
+
+        arena.push(&1u32).unwrap();
+        let big = arena.push_bytes(&[0xAA; 0x2800]).unwrap();
+        assert_eq!(process.try_read_bytes(big, 0x2800).unwrap(), vec![0xAA; 0x2800]);
+        assert_eq!(arena.blocks().len(), 2);
+        assert!(arena.blocks()[1].end - arena.blocks()[1].start >= 0x2800);
+    }
+}
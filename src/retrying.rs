@@ -0,0 +1,143 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use crate::*;
+use crate::forward::forward_process_traits;
+
+/// How long a Retrying wrapper waits between attempts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    /// Retry immediately
+    None,
+    /// Wait the same amount of time before every retry
+    Constant(Duration),
+    /// Wait initial before the first retry and double the wait for every retry after, up to max
+    Exponential { initial: Duration, max: Duration },
+}
+
+impl Backoff {
+    /// Returns how long to wait before the specified retry, starting at zero
+    pub fn delay(&self, retry: u32) -> Duration {
+        match *self {
+            Backoff::None => Duration::ZERO,
+            Backoff::Constant(delay) => delay,
+            Backoff::Exponential { initial, max } => {
+                initial.checked_mul(1u32.checked_shl(retry).unwrap_or(u32::MAX))
+                    .unwrap_or(max)
+                    .min(max)
+            }
+        }
+    }
+}
+
+/// Wraps a memory backend and retries failed reads and writes. A call is attempted up to
+/// `attempts` times before the failure is returned to the caller.
+pub struct Retrying<M> {
+    inner: M,
+    attempts: u32,
+    backoff: Backoff,
+    retries: AtomicU64,
+}
+
+impl<M> Retrying<M> {
+    /// Creates a new wrapper that makes up to three attempts without waiting in between
+    pub fn new(inner: M) -> Self {
+        Self { inner, attempts: 3, backoff: Backoff::None, retries: AtomicU64::new(0) }
+    }
+
+    /// Sets the maximum number of attempts for each call, including the first
+    pub fn attempts(mut self, attempts: u32) -> Self {
+        assert_ne!(attempts, 0, "attempts must be greater than zero");
+        self.attempts = attempts;
+        self
+    }
+
+    /// Sets how long to wait between attempts
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Returns the number of times a call has been retried
+    pub fn retries(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    /// Returns a reference to the inner type
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    /// Consumes the wrapper and returns the inner type
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    fn retry(&self, mut f: impl FnMut() -> Option<()>) -> Option<()> {
+        for retry in 0..self.attempts - 1 {
+            if f().is_some() {
+                return Some(());
+            }
+            self.retries.fetch_add(1, Ordering::Relaxed);
+            let delay = self.backoff.delay(retry);
+            if !delay.is_zero() {
+                std::thread::sleep(delay);
+            }
+        }
+        f()
+    }
+}
+
+impl<M: MemoryRead> MemoryRead for Retrying<M> {
+    fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        self.retry(|| self.inner.try_read_bytes_into(address, buffer))
+    }
+}
+
+impl<M: MemoryWrite> MemoryWrite for Retrying<M> {
+    fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
+        self.retry(|| self.inner.try_write_bytes(address, buffer))
+    }
+}
+
+forward_process_traits!(Retrying.inner: ModuleList, ProcessInfo, MemoryAllocate, MemoryProtect, MemoryRegions);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flaky(n: u64) -> FaultInjector<MemoryBuffer<Vec<u8>>> {
+        FaultInjector::new(MemoryBuffer::new(0, vec![0u8; 0x100]), 0).fail_every(n)
+    }
+
+    #[test]
+    fn test_retry_transient_failures() {
+        let retrying = Retrying::new(flaky(2)).attempts(2);
+        for i in 0..10 {
+            retrying.try_write(i, &(i as u8)).unwrap();
+            assert_eq!(retrying.read::<u8>(i), i as u8);
+        }
+        assert_eq!(retrying.inner().calls(), 39);
+        assert_eq!(retrying.retries(), 19);
+    }
+
+    #[test]
+    fn test_gives_up() {
+        let retrying = Retrying::new(flaky(1)).attempts(4);
+        assert!(retrying.try_read::<u8>(0).is_none());
+        assert_eq!(retrying.inner().calls(), 4);
+        assert_eq!(retrying.retries(), 3);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let ms = Duration::from_millis;
+        let exponential = Backoff::Exponential { initial: ms(10), max: ms(50) };
+
+        assert_eq!(Backoff::None.delay(3), Duration::ZERO);
+        assert_eq!(Backoff::Constant(ms(5)).delay(3), ms(5));
+        assert_eq!(exponential.delay(0), ms(10));
+        assert_eq!(exponential.delay(2), ms(40));
+        assert_eq!(exponential.delay(3), ms(50));
+        assert_eq!(exponential.delay(100), ms(50));
+    }
+}
@@ -0,0 +1,219 @@
+use std::sync::Arc;
+use crate::*;
+
+/// A reference counted handle to an AttachedProcess. Cloning the handle is cheap and does not
+/// require the backend to be Clone, and the handle is Send and Sync whenever the backend and
+/// its context are, so one process can be used from several threads without lifetimes.
+pub struct SharedProcess<T: GetContext + 'static> {
+    process: Arc<AttachedProcess<'static, T>>,
+}
+
+impl<T: GetContext + 'static> SharedProcess<T> {
+    pub fn new(process: AttachedProcess<'static, T>) -> Self {
+        Self { process: Arc::new(process) }
+    }
+
+    /// Attaches to a process of name process_name using a shared api.
+    /// If no process is found None is returned
+    pub fn attach(api: Arc<T>, process_name: &str) -> Option<Self> {
+        let context = api.get_context_from_name(process_name)?;
+        Some(Self::new(AttachedProcess::new_shared(api, context)))
+    }
+
+    /// Attaches to a process by a pid using a shared api. If the pid does not exist, this will return None
+    pub fn attach_pid(api: Arc<T>, pid: u32) -> Option<Self> {
+        let context = api.get_context_from_pid(pid)?;
+        Some(Self::new(AttachedProcess::new_shared(api, context)))
+    }
+
+    pub fn api(&self) -> &T {
+        self.process.api()
+    }
+
+    pub fn context(&self) -> &T::Context {
+        self.process.context()
+    }
+
+    /// Returns the inner AttachedProcess
+    pub fn process(&self) -> &AttachedProcess<'static, T> {
+        &self.process
+    }
+}
+
+impl<T: GetContext + 'static> Clone for SharedProcess<T> {
+    fn clone(&self) -> Self {
+        Self { process: self.process.clone() }
+    }
+}
+
+impl<T: GetContext + 'static> From<AttachedProcess<'static, T>> for SharedProcess<T> {
+    fn from(process: AttachedProcess<'static, T>) -> Self {
+        Self::new(process)
+    }
+}
+
+impl<T: MemoryReadPid + 'static> MemoryRead for SharedProcess<T> {
+    fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        self.process.try_read_bytes_into(address, buffer)
+    }
+}
+
+impl<T: MemoryWritePid + 'static> MemoryWrite for SharedProcess<T> {
+    fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
+        self.process.try_write_bytes(address, buffer)
+    }
+}
+
+impl<T: ModuleListPid + 'static> ModuleList for SharedProcess<T> {
+    fn get_module_list(&self) -> Vec<Module> {
+        self.process.get_module_list()
+    }
+
+    fn get_module(&self, name: &str) -> Option<Module> {
+        self.process.get_module(name)
+    }
+
+    fn get_main_module(&self) -> Module {
+        self.process.get_main_module()
+    }
+
+    fn get_module_info_list(&self) -> Vec<ModuleInfo> {
+        self.process.get_module_info_list()
+    }
+}
+
+impl<T: ProcessInfoPid + 'static> ProcessInfo for SharedProcess<T> {
+    fn process_name(&self) -> String {
+        self.process.process_name()
+    }
+
+    fn peb_base_address(&self) -> u64 {
+        self.process.peb_base_address()
+    }
+
+    fn pid(&self) -> u32 {
+        self.process.pid()
+    }
+}
+
+impl<T: MemoryAllocatePid + 'static> MemoryAllocate for SharedProcess<T> {
+    fn allocate(&self, size: u64, protection: MemoryProtection) -> Result<u64, MemoryAllocateError> {
+        self.process.allocate(size, protection)
+    }
+
+    fn free(&self, base: u64, size: u64) -> Result<(), MemoryAllocateError> {
+        self.process.free(base, size)
+    }
+}
+
+impl<T: MemoryProtectPid + 'static> MemoryProtect for SharedProcess<T> {
+    fn set_protection(&self, range: MemoryRange, protection: MemoryProtection) -> Result<MemoryProtection, MemoryProtectError> {
+        self.process.set_protection(range, protection)
+    }
+}
+
+impl<T: MemoryRegionsPid + 'static> MemoryRegions for SharedProcess<T> {
+    fn query_region(&self, address: u64) -> Option<MemoryRegion> {
+        self.process.query_region(address)
+    }
+}
+
+#[cfg(feature = "kernel")]
+impl<T: TranslatePhysicalPid + 'static> TranslatePhysical for SharedProcess<T> {
+    fn physical_address(&self, virtual_address: u64) -> Option<u64> {
+        self.process.physical_address(virtual_address)
+    }
+
+    fn translate(&self, virtual_address: u64) -> Result<Translation, TranslationError> {
+        self.process.translate(virtual_address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// A backend that is Send and Sync but not Clone, with one process per context
+    struct FakeApi {
+        memory: Mutex<Vec<u8>>,
+    }
+
+    impl GetContext for FakeApi {
+        type Context = u32;
+
+        fn get_context_from_name(&self, process_name: &str) -> Option<u32> {
+            (process_name == "game.exe").then_some(1)
+        }
+
+        fn get_context_from_pid(&self, pid: u32) -> Option<u32> {
+            (pid == 1).then_some(pid)
+        }
+
+        fn get_current_context(&self) -> u32 {
+            0
+        }
+    }
+
+    impl MemoryReadPid for FakeApi {
+        fn try_read_bytes_into_pid(&self, _pid: &u32, address: u64, buffer: &mut [u8]) -> Option<()> {
+            let memory = self.memory.lock().unwrap();
+            buffer.copy_from_slice(memory.get(address as usize..address as usize + buffer.len())?);
+            Some(())
+        }
+    }
+
+    impl MemoryWritePid for FakeApi {
+        fn try_write_bytes_pid(&self, _pid: &u32, address: u64, buffer: &[u8]) -> Option<()> {
+            let mut memory = self.memory.lock().unwrap();
+            memory.get_mut(address as usize..address as usize + buffer.len())?.copy_from_slice(buffer);
+            Some(())
+        }
+    }
+
+    impl ProcessInfoPid for FakeApi {
+        fn process_name(&self, _pid: &u32) -> String {
+            "game.exe".to_string()
+        }
+
+        fn peb_base_address(&self, _pid: &u32) -> u64 {
+            0
+        }
+
+        fn pid(&self, pid: &u32) -> u32 {
+            *pid
+        }
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+
+    #[test]
+    fn test_auto_traits() {
+        assert_shareable::<SharedProcess<FakeApi>>();
+        assert_send_sync::<AttachedProcess<'static, FakeApi>>();
+        assert_send_sync::<MaybeOwned<'static, FakeApi>>();
+    }
+
+    #[test]
+    fn test_threads() {
+        let api = Arc::new(FakeApi { memory: Mutex::new(vec![0; 0x100]) });
+        let process = SharedProcess::attach(api.clone(), "game.exe").unwrap();
+        assert!(SharedProcess::attach(api.clone(), "other.exe").is_none());
+        assert_eq!(SharedProcess::attach_pid(api, 1).unwrap().pid(), 1);
+
+        let threads: Vec<_> = (0..4u64)
+            .map(|i| {
+                let process = process.clone();
+                std::thread::spawn(move || process.write(i * 8, &i))
+            })
+            .collect();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+
+        for i in 0..4u64 {
+            assert_eq!(process.read::<u64>(i * 8), i);
+        }
+        assert_eq!(process.process_name(), "game.exe");
+    }
+}
@@ -0,0 +1,693 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use crate::*;
+
+/// A named location in a module relative to the module base
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub rva: u64,
+    /// The size of the symbol in bytes, if known
+    pub size: Option<u64>,
+}
+
+impl Symbol {
+    /// Returns the address of the symbol in a loaded module
+    pub fn address(&self, module: &Module) -> u64 {
+        module.base + self.rva
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum SymbolError {
+    Io(std::io::Error),
+    InvalidElf(String),
+    InvalidLine { line: usize, text: String },
+}
+
+impl From<std::io::Error> for SymbolError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Represents a type that can resolve symbols in a module
+pub trait SymbolProvider {
+    /// Returns the symbol with the specified name in the module.
+    /// If the provider has no symbols for the module or the name does not exist, returns None
+    fn symbol_by_name(&self, module: &Module, name: &str) -> Option<Symbol>;
+
+    /// Returns the closest symbol at or before the rva in the module. If the size of
+    /// that symbol is known and the rva is past its end, returns None
+    fn symbol_for_rva(&self, module: &Module, rva: u64) -> Option<Symbol>;
+}
+
+/// A list of symbols for a single module that can be searched by name or address
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    /// Sorted by rva
+    symbols: Vec<Symbol>,
+    by_name: HashMap<String, usize>,
+}
+
+impl SymbolTable {
+    pub fn new(mut symbols: Vec<Symbol>) -> Self {
+        symbols.sort_by(|a, b| a.rva.cmp(&b.rva).then_with(|| a.name.cmp(&b.name)));
+        symbols.dedup_by(|a, b| a.rva == b.rva && a.name == b.name);
+        let by_name = symbols.iter().enumerate().map(|(i, s)| (s.name.clone(), i)).collect();
+        Self { symbols, by_name }
+    }
+
+    /// Returns every symbol ordered by rva
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&Symbol> {
+        self.by_name.get(name).map(|i| &self.symbols[*i])
+    }
+
+    /// Returns the closest symbol at or before the rva
+    pub fn by_rva(&self, rva: u64) -> Option<&Symbol> {
+        let index = self.symbols.partition_point(|s| s.rva <= rva).checked_sub(1)?;
+        let symbol = &self.symbols[index];
+        match symbol.size {
+            Some(size) if size > 0 && rva >= symbol.rva + size => None,
+            _ => Some(symbol),
+        }
+    }
+
+    /// Reads the .symtab and .dynsym sections of an ELF file on disk
+    pub fn from_elf_file(path: impl AsRef<Path>) -> Result<Self, SymbolError> {
+        Self::from_elf(&std::fs::read(path)?)
+    }
+
+    /// Reads the .symtab and .dynsym sections of an ELF image. Symbol values are converted
+    /// into rvas relative to the lowest loadable segment
+    pub fn from_elf(data: &[u8]) -> Result<Self, SymbolError> {
+        ElfFile::parse(data)?.symbols().map(Self::new)
+    }
+
+    /// Parses a map file created by the MSVC linker with /MAP. Rvas are calculated from the
+    /// preferred load address. Absolute symbols are skipped
+    pub fn from_msvc_map(text: &str) -> Result<Self, SymbolError> {
+        let mut preferred_base = None;
+        let mut symbols = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if let Some(base) = line.strip_prefix("Preferred load address is ") {
+                preferred_base = Some(u64::from_str_radix(base.trim(), 16).map_err(|_| invalid_line(i, line))?);
+                continue;
+            }
+
+            // 0001:00000010       ?helper@@YAHH@Z            0000000140001010 f   main.obj
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let is_symbol = tokens.len() >= 3
+                && tokens[0].len() == 13
+                && tokens[0].as_bytes()[4] == b':'
+                && tokens[0].chars().all(|c| c == ':' || c.is_ascii_hexdigit());
+            if !is_symbol {
+                continue;
+            }
+
+            let base = preferred_base.ok_or_else(|| invalid_line(i, line))?;
+            let address = u64::from_str_radix(tokens[2], 16).map_err(|_| invalid_line(i, line))?;
+            if address < base {
+                continue;
+            }
+            symbols.push(Symbol { name: tokens[1].to_string(), rva: address - base, size: None });
+        }
+
+        Ok(Self::new(symbols))
+    }
+
+    /// Parses a map file created by GNU ld with -Map. Rvas are calculated from image_base
+    pub fn from_gnu_map(text: &str, image_base: u64) -> Result<Self, SymbolError> {
+        let mut symbols = Vec::new();
+
+        for line in text.lines() {
+            //                 0x0000000000401020                helper
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let [address, name] = tokens[..] else { continue };
+            let Some(address) = address.strip_prefix("0x") else { continue };
+            if name.starts_with('.') || name.contains(['=', '(', ')', '*']) || !line.starts_with(' ') {
+                continue;
+            }
+            let Ok(address) = u64::from_str_radix(address, 16) else { continue };
+            if address < image_base {
+                continue;
+            }
+            symbols.push(Symbol { name: name.to_string(), rva: address - image_base, size: None });
+        }
+
+        Ok(Self::new(symbols))
+    }
+
+    /// Parses a text file with one `name=rva` pair per line. Rvas are hex with an optional 0x prefix.
+    /// Blank lines and lines starting with # are skipped
+    pub fn from_rva_list(text: &str) -> Result<Self, SymbolError> {
+        let mut symbols = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, rva) = line.split_once('=').ok_or_else(|| invalid_line(i, line))?;
+            let rva = rva.trim();
+            let rva = rva.strip_prefix("0x").or_else(|| rva.strip_prefix("0X")).unwrap_or(rva);
+            let rva = u64::from_str_radix(rva, 16).map_err(|_| invalid_line(i, line))?;
+            symbols.push(Symbol { name: name.trim().to_string(), rva, size: None });
+        }
+
+        Ok(Self::new(symbols))
+    }
+}
+
+/// Returns base + index * size, or an error if a corrupt file makes it overflow
+fn offset_of(base: u64, index: u64, size: u64) -> Result<u64, SymbolError> {
+    index.checked_mul(size).and_then(|o| base.checked_add(o))
+        .ok_or_else(|| SymbolError::InvalidElf(format!("offset {:#X} + {:#X} * {:#X} overflows", base, index, size)))
+}
+
+fn invalid_line(index: usize, text: &str) -> SymbolError {
+    SymbolError::InvalidLine { line: index + 1, text: text.to_string() }
+}
+
+/// A minimal reader for the parts of 32 and 64 bit ELF files needed to read symbols
+struct ElfFile<'a> {
+    data: &'a [u8],
+    is_64: bool,
+    big_endian: bool,
+}
+
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_DYNSYM: u32 = 11;
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+const STT_TLS: u8 = 6;
+
+impl<'a> ElfFile<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, SymbolError> {
+        if data.len() < 0x34 || &data[..4] != b"\x7FELF" {
+            return Err(SymbolError::InvalidElf("missing ELF magic".to_string()));
+        }
+        let is_64 = match data[4] {
+            1 => false,
+            2 => true,
+            n => return Err(SymbolError::InvalidElf(format!("invalid class {}", n))),
+        };
+        let big_endian = match data[5] {
+            1 => false,
+            2 => true,
+            n => return Err(SymbolError::InvalidElf(format!("invalid data encoding {}", n))),
+        };
+        Ok(Self { data, is_64, big_endian })
+    }
+
+    fn bytes<const N: usize>(&self, offset: u64) -> Result<[u8; N], SymbolError> {
+        usize::try_from(offset).ok()
+            .and_then(|offset| self.data.get(offset..offset.checked_add(N)?))
+            .map(|b| b.try_into().unwrap())
+            .ok_or_else(|| SymbolError::InvalidElf(format!("offset {:#X} is out of bounds", offset)))
+    }
+
+    fn u8(&self, offset: u64) -> Result<u8, SymbolError> {
+        Ok(self.bytes::<1>(offset)?[0])
+    }
+
+    fn u16(&self, offset: u64) -> Result<u16, SymbolError> {
+        let b = self.bytes(offset)?;
+        Ok(if self.big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+    }
+
+    fn u32(&self, offset: u64) -> Result<u32, SymbolError> {
+        let b = self.bytes(offset)?;
+        Ok(if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+
+    fn u64(&self, offset: u64) -> Result<u64, SymbolError> {
+        let b = self.bytes(offset)?;
+        Ok(if self.big_endian { u64::from_be_bytes(b) } else { u64::from_le_bytes(b) })
+    }
+
+    /// Reads an address sized value
+    fn addr(&self, offset: u64) -> Result<u64, SymbolError> {
+        if self.is_64 { self.u64(offset) } else { self.u32(offset).map(u64::from) }
+    }
+
+    fn string(&self, offset: u64) -> Result<String, SymbolError> {
+        let start = usize::try_from(offset).ok().filter(|o| *o < self.data.len())
+            .ok_or_else(|| SymbolError::InvalidElf(format!("string at {:#X} is out of bounds", offset)))?;
+        let len = self.data[start..].iter().position(|b| *b == 0)
+            .ok_or_else(|| SymbolError::InvalidElf("unterminated string".to_string()))?;
+        Ok(String::from_utf8_lossy(&self.data[start..start + len]).into_owned())
+    }
+
+    /// Returns the lowest virtual address of a loadable segment rounded down to a page
+    fn load_base(&self) -> Result<u64, SymbolError> {
+        let (phoff, phentsize, phnum) = if self.is_64 {
+            (self.u64(0x20)?, self.u16(0x36)?, self.u16(0x38)?)
+        } else {
+            (self.u32(0x1C)? as u64, self.u16(0x2A)?, self.u16(0x2C)?)
+        };
+
+        let mut base = None::<u64>;
+        for i in 0..phnum as u64 {
+            let header = offset_of(phoff, i, phentsize as u64)?;
+            if self.u32(header)? != PT_LOAD {
+                continue;
+            }
+            let vaddr = self.addr(offset_of(header, 1, if self.is_64 { 0x10 } else { 0x8 })?)?;
+            base = Some(base.map_or(vaddr, |b| b.min(vaddr)));
+        }
+        Ok(base.unwrap_or(0) & !0xFFF)
+    }
+
+    fn symbols(&self) -> Result<Vec<Symbol>, SymbolError> {
+        let (shoff, shentsize, shnum) = if self.is_64 {
+            (self.u64(0x28)?, self.u16(0x3A)?, self.u16(0x3C)?)
+        } else {
+            (self.u32(0x20)? as u64, self.u16(0x2E)?, self.u16(0x30)?)
+        };
+        let base = self.load_base()?;
+        let section = |i: u64| offset_of(shoff, i, shentsize as u64);
+
+        let mut symbols = Vec::new();
+        for i in 0..shnum as u64 {
+            let header = section(i)?;
+            let field = |offset: u64| offset_of(header, 1, offset);
+            let sh_type = self.u32(field(4)?)?;
+            if sh_type != SHT_SYMTAB && sh_type != SHT_DYNSYM {
+                continue;
+            }
+
+            let (offset, size, link, entsize) = if self.is_64 {
+                (self.u64(field(0x18)?)?, self.u64(field(0x20)?)?, self.u32(field(0x28)?)?, self.u64(field(0x38)?)?)
+            } else {
+                (self.u32(field(0x10)?)? as u64, self.u32(field(0x14)?)? as u64, self.u32(field(0x18)?)?, self.u32(field(0x24)?)? as u64)
+            };
+            if entsize == 0 {
+                return Err(SymbolError::InvalidElf("symbol table has an entry size of zero".to_string()));
+            }
+            let strtab = self.addr(offset_of(section(link as u64)?, 1, if self.is_64 { 0x18 } else { 0x10 })?)?;
+            let entsize = usize::try_from(entsize)
+                .map_err(|_| SymbolError::InvalidElf(format!("invalid symbol entry size {:#X}", entsize)))?;
+
+            for sym in (offset..offset_of(offset, 1, size)?).step_by(entsize) {
+                let field = |offset: u64| offset_of(sym, 1, offset);
+                let (name, info, shndx, value, size) = if self.is_64 {
+                    (self.u32(sym)?, self.u8(field(4)?)?, self.u16(field(6)?)?, self.u64(field(8)?)?, self.u64(field(16)?)?)
+                } else {
+                    (self.u32(sym)?, self.u8(field(12)?)?, self.u16(field(14)?)?, self.u32(field(4)?)? as u64, self.u32(field(8)?)? as u64)
+                };
+                if name == 0 || shndx == 0 || matches!(info & 0xF, STT_SECTION | STT_FILE | STT_TLS) || value < base {
+                    continue;
+                }
+                symbols.push(Symbol {
+                    name: self.string(offset_of(strtab, 1, name as u64)?)?,
+                    rva: value - base,
+                    size: (size > 0).then_some(size),
+                });
+            }
+        }
+
+        Ok(symbols)
+    }
+}
+
+impl SymbolProvider for SymbolTable {
+    fn symbol_by_name(&self, _module: &Module, name: &str) -> Option<Symbol> {
+        self.by_name(name).cloned()
+    }
+
+    fn symbol_for_rva(&self, _module: &Module, rva: u64) -> Option<Symbol> {
+        self.by_rva(rva).cloned()
+    }
+}
+
+/// Symbol tables for several modules, looked up by module name
+#[derive(Debug, Clone, Default)]
+pub struct ModuleSymbols {
+    tables: Vec<(String, SymbolTable)>,
+}
+
+impl ModuleSymbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the symbols for the module with the specified name or path
+    pub fn insert(&mut self, module_name: impl Into<String>, table: SymbolTable) {
+        self.tables.push((module_name.into(), table));
+    }
+
+    fn table(&self, module: &Module) -> Option<&SymbolTable> {
+        self.tables.iter().find(|(name, _)| module.matches_name(name)).map(|(_, table)| table)
+    }
+}
+
+impl SymbolProvider for ModuleSymbols {
+    fn symbol_by_name(&self, module: &Module, name: &str) -> Option<Symbol> {
+        self.table(module)?.by_name(name).cloned()
+    }
+
+    fn symbol_for_rva(&self, module: &Module, rva: u64) -> Option<Symbol> {
+        self.table(module)?.by_rva(rva).cloned()
+    }
+}
+
+/// Loads symbols from the ELF file of each module on disk the first time it is needed.
+/// If the module name is not a path to an existing file, the search directories are checked
+/// for a file with the same name
+#[derive(Default)]
+pub struct ElfSymbolProvider {
+    search_dirs: Vec<PathBuf>,
+    cache: Mutex<HashMap<String, Option<SymbolTable>>>,
+}
+
+impl ElfSymbolProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a directory to search for module files in
+    pub fn search_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.search_dirs.push(dir.into());
+        self
+    }
+
+    fn find_file(&self, module: &Module) -> Option<PathBuf> {
+        let path = Path::new(&module.name);
+        if path.is_file() {
+            return Some(path.to_path_buf());
+        }
+        self.search_dirs.iter()
+            .map(|dir| dir.join(module.base_name()))
+            .find(|path| path.is_file())
+    }
+
+    fn with_table<R>(&self, module: &Module, f: impl FnOnce(&SymbolTable) -> Option<R>) -> Option<R> {
+        let mut cache = self.cache.lock().unwrap();
+        let table = cache.entry(module.name.clone())
+            .or_insert_with(|| SymbolTable::from_elf_file(self.find_file(module)?).ok());
+        f(table.as_ref()?)
+    }
+}
+
+impl SymbolProvider for ElfSymbolProvider {
+    fn symbol_by_name(&self, module: &Module, name: &str) -> Option<Symbol> {
+        self.with_table(module, |t| t.by_name(name).cloned())
+    }
+
+    fn symbol_for_rva(&self, module: &Module, rva: u64) -> Option<Symbol> {
+        self.with_table(module, |t| t.by_rva(rva).cloned())
+    }
+}
+
+/// Wraps a ModuleList and resolves addresses to symbols using a list of SymbolProviders.
+/// Providers are searched in the order they were added. format_address produces
+/// `module!symbol+0x12` when a symbol is found
+pub struct SymbolizedProcess<M> {
+    inner: M,
+    providers: Vec<Box<dyn SymbolProvider + Send + Sync>>,
+}
+
+impl<M: ModuleList> SymbolizedProcess<M> {
+    pub fn new(inner: M) -> Self {
+        Self { inner, providers: Vec::new() }
+    }
+
+    /// Adds a symbol provider that is searched after the existing providers
+    pub fn with_provider(mut self, provider: impl SymbolProvider + Send + Sync + 'static) -> Self {
+        self.providers.push(Box::new(provider));
+        self
+    }
+
+    /// Returns the module and symbol that contain the address
+    pub fn symbol_for_address(&self, address: u64) -> Option<(Module, Symbol)> {
+        let module = self.inner.module_for_address(address)?;
+        let rva = address - module.base;
+        let symbol = self.providers.iter().find_map(|p| p.symbol_for_rva(&module, rva))?;
+        Some((module, symbol))
+    }
+
+    /// Returns the address of a symbol in the module with the specified name
+    pub fn resolve(&self, module_name: &str, symbol: &str) -> Option<u64> {
+        let module = self.inner.get_module(module_name)?;
+        self.providers.iter()
+            .find_map(|p| p.symbol_by_name(&module, symbol))
+            .map(|s| s.address(&module))
+    }
+
+    /// Returns a reference to the inner type
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    /// Consumes the wrapper and returns the inner type
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+}
+
+impl<M: ModuleList> ModuleList for SymbolizedProcess<M> {
+    fn get_module_list(&self) -> Vec<Module> {
+        self.inner.get_module_list()
+    }
+
+    fn get_module(&self, name: &str) -> Option<Module> {
+        self.inner.get_module(name)
+    }
+
+    fn get_main_module(&self) -> Module {
+        self.inner.get_main_module()
+    }
+
+    fn get_module_info_list(&self) -> Vec<ModuleInfo> {
+        self.inner.get_module_info_list()
+    }
+
+    fn format_address(&self, address: u64) -> String {
+        match self.symbol_for_address(address) {
+            Some((module, symbol)) => {
+                let offset = address - symbol.address(&module);
+                if offset == 0 {
+                    format!("{}!{}", module.base_name(), symbol.name)
+                } else {
+                    format!("{}!{}+{:#X}", module.base_name(), symbol.name, offset)
+                }
+            }
+            None => self.inner.format_address(address),
+        }
+    }
+}
+
+impl<M: ModuleList + MemoryRead> MemoryRead for SymbolizedProcess<M> {
+    fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        self.inner.try_read_bytes_into(address, buffer)
+    }
+}
+
+impl<M: ModuleList + MemoryWrite> MemoryWrite for SymbolizedProcess<M> {
+    fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
+        self.inner.try_write_bytes(address, buffer)
+    }
+}
+
+impl<M: ModuleList + ProcessInfo> ProcessInfo for SymbolizedProcess<M> {
+    fn process_name(&self) -> String {
+        self.inner.process_name()
+    }
+
+    fn peb_base_address(&self) -> u64 {
+        self.inner.peb_base_address()
+    }
+
+    fn pid(&self) -> u32 {
+        self.inner.pid()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a little endian ELF64 shared object with one loadable segment at 0x400000
+    /// and a .symtab containing the symbols
+    fn build_elf(symbols: &[(&str, u64, u64, u8)]) -> Vec<u8> {
+        let mut strtab = vec![0u8];
+        let mut symtab = vec![0u8; 24];
+        for (name, value, size, kind) in symbols {
+            let name_offset = strtab.len() as u32;
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+            symtab.extend_from_slice(&name_offset.to_le_bytes());
+            symtab.push(0x10 | kind);
+            symtab.push(0);
+            symtab.extend_from_slice(&1u16.to_le_bytes());
+            symtab.extend_from_slice(&value.to_le_bytes());
+            symtab.extend_from_slice(&size.to_le_bytes());
+        }
+
+        let phoff = 0x40u64;
+        let strtab_offset = phoff + 0x38;
+        let symtab_offset = strtab_offset + strtab.len() as u64;
+        let shoff = symtab_offset + symtab.len() as u64;
+
+        let mut elf = vec![0u8; 0x40];
+        elf[..7].copy_from_slice(b"\x7FELF\x02\x01\x01");
+        elf[0x10..0x12].copy_from_slice(&3u16.to_le_bytes());
+        elf[0x20..0x28].copy_from_slice(&phoff.to_le_bytes());
+        elf[0x28..0x30].copy_from_slice(&shoff.to_le_bytes());
+        elf[0x36..0x38].copy_from_slice(&0x38u16.to_le_bytes());
+        elf[0x38..0x3A].copy_from_slice(&1u16.to_le_bytes());
+        elf[0x3A..0x3C].copy_from_slice(&0x40u16.to_le_bytes());
+        elf[0x3C..0x3E].copy_from_slice(&3u16.to_le_bytes());
+
+        let mut phdr = vec![0u8; 0x38];
+        phdr[..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+        phdr[0x10..0x18].copy_from_slice(&0x400000u64.to_le_bytes());
+        elf.extend_from_slice(&phdr);
+        elf.extend_from_slice(&strtab);
+        elf.extend_from_slice(&symtab);
+
+        let section = |sh_type: u32, offset: u64, size: u64, link: u32, entsize: u64| {
+            let mut s = vec![0u8; 0x40];
+            s[4..8].copy_from_slice(&sh_type.to_le_bytes());
+            s[0x18..0x20].copy_from_slice(&offset.to_le_bytes());
+            s[0x20..0x28].copy_from_slice(&size.to_le_bytes());
+            s[0x28..0x2C].copy_from_slice(&link.to_le_bytes());
+            s[0x38..0x40].copy_from_slice(&entsize.to_le_bytes());
+            s
+        };
+        elf.extend(section(0, 0, 0, 0, 0));
+        elf.extend(section(3, strtab_offset, strtab.len() as u64, 0, 0));
+        elf.extend(section(SHT_SYMTAB, symtab_offset, symtab.len() as u64, 1, 24));
+        elf
+    }
+
+    #[test]
+    fn test_elf_symbols() {
+        let elf = build_elf(&[
+            ("main", 0x401000, 0x20, 2),
+            ("helper", 0x401040, 0x10, 2),
+            ("global", 0x402000, 8, 1),
+            (".text", 0x401000, 0, STT_SECTION),
+        ]);
+        let table = SymbolTable::from_elf(&elf).unwrap();
+
+        assert_eq!(table.symbols().len(), 3);
+        assert_eq!(table.by_name("helper").unwrap().rva, 0x1040);
+        assert_eq!(table.by_rva(0x1010).unwrap().name, "main");
+        assert!(table.by_rva(0x1030).is_none());
+        assert_eq!(table.by_rva(0x1045).unwrap().name, "helper");
+        assert!(table.by_rva(0x500).is_none());
+
+        assert!(SymbolTable::from_elf(&elf[..0x60]).is_err());
+        assert!(SymbolTable::from_elf(b"MZ").is_err());
+    }
+
+    #[test]
+    fn test_corrupt_elf() {
+        let elf = build_elf(&[("main", 0x401000, 0x20, 2)]);
+        let shoff = u64::from_le_bytes(elf[0x28..0x30].try_into().unwrap()) as usize;
+        let symtab = shoff + 2 * 0x40;
+        let corrupt = |offset: usize, value: u64| {
+            let mut elf = elf.clone();
+            elf[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+            SymbolTable::from_elf(&elf)
+        };
+
+        assert!(matches!(corrupt(0x20, u64::MAX - 8), Err(SymbolError::InvalidElf(_))), "program header offset");
+        assert!(matches!(corrupt(0x28, u64::MAX - 8), Err(SymbolError::InvalidElf(_))), "section header offset");
+        assert!(matches!(corrupt(symtab + 0x20, u64::MAX), Err(SymbolError::InvalidElf(_))), "symbol table size");
+        assert!(matches!(corrupt(symtab + 0x38, 0), Err(SymbolError::InvalidElf(_))), "symbol entry size");
+        assert!(matches!(corrupt(shoff + 0x40 + 0x18, u64::MAX), Err(SymbolError::InvalidElf(_))), "string table offset");
+    }
+
+    #[test]
+    fn test_msvc_map() {
+        let map = "
+ game
+
+ Timestamp is 5f1e2d3c (Mon Jul 27 00:00:00 2020)
+
+ Preferred load address is 0000000140000000
+
+  Address         Publics by Value              Rva+Base               Lib:Object
+
+ 0000:00000000       __guard_flags              0000000000000000     <absolute>
+ 0001:00000000       main                       0000000140001000 f   main.obj
+ 0001:00000010       ?helper@@YAHH@Z            0000000140001010 f   main.obj
+
+ entry point at        0001:00000000
+";
+        let table = SymbolTable::from_msvc_map(map).unwrap();
+        assert_eq!(table.symbols().len(), 2);
+        assert_eq!(table.by_name("main").unwrap().rva, 0x1000);
+        assert_eq!(table.by_rva(0x1018).unwrap().name, "?helper@@YAHH@Z");
+    }
+
+    #[test]
+    fn test_gnu_map() {
+        let map = "
+ .text          0x0000000000401000       0x50 main.o
+                0x0000000000401000                main
+                0x0000000000401020                helper
+                0x0000000000400000                PROVIDE (__executable_start = SEGMENT_START (\"text-segment\", 0x400000))
+                0x0000000000404000                . = ALIGN (0x8)
+";
+        let table = SymbolTable::from_gnu_map(map, 0x400000).unwrap();
+        assert_eq!(table.symbols().len(), 2);
+        assert_eq!(table.by_name("helper").unwrap().rva, 0x1020);
+    }
+
+    #[test]
+    fn test_rva_list() {
+        let table = SymbolTable::from_rva_list("# offsets\nLocalPlayer=0x1234\n\nEntityList = 5678\n").unwrap();
+        assert_eq!(table.by_name("LocalPlayer").unwrap().rva, 0x1234);
+        assert_eq!(table.by_name("EntityList").unwrap().rva, 0x5678);
+        assert!(matches!(SymbolTable::from_rva_list("a=1\nbad line"), Err(SymbolError::InvalidLine { line: 2, .. })));
+    }
+
+    #[test]
+    fn test_elf_provider() {
+        let dir = std::env::temp_dir().join(format!("memlib-symbols-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("libgame.so"), build_elf(&[("tick", 0x401100, 0x80, 2)])).unwrap();
+
+        let provider = ElfSymbolProvider::new().search_dir(&dir);
+        let module = Module { name: "libgame.so".to_string(), base: 0x7F0000000000, size: 0x10000 };
+        let missing = Module { name: "libother.so".to_string(), ..module.clone() };
+
+        assert_eq!(provider.symbol_by_name(&module, "tick").unwrap().address(&module), 0x7F0000001100);
+        assert_eq!(provider.symbol_for_rva(&module, 0x1110).unwrap().name, "tick");
+        assert!(provider.symbol_by_name(&missing, "tick").is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_symbolized_process() {
+        let process = MockProcess::new();
+        process.add_module("game.exe", 0x140000000, vec![0; 0x3000], MemoryProtection::EXECUTE_READ);
+        process.add_module("C:\\Windows\\System32\\ntdll.dll", 0x7FF800000000, vec![0; 0x2000], MemoryProtection::EXECUTE_READ);
+
+        let mut symbols = ModuleSymbols::new();
+        symbols.insert("ntdll.dll", SymbolTable::from_rva_list("NtClose=0x1000").unwrap());
+        let process = SymbolizedProcess::new(process)
+            .with_provider(symbols)
+            .with_provider(ModuleSymbols::new());
+
+        assert_eq!(process.format_address(0x7FF800001012), "ntdll.dll!NtClose+0x12");
+        assert_eq!(process.format_address(0x7FF800001000), "ntdll.dll!NtClose");
+        assert_eq!(process.format_address(0x7FF800000010), "ntdll.dll+0x10");
+        assert_eq!(process.format_address(0x140000010), "game.exe+0x10");
+        assert_eq!(process.resolve("ntdll.dll", "NtClose"), Some(0x7FF800001000));
+        assert_eq!(process.resolve("game.exe", "NtClose"), None);
+    }
+}
@@ -0,0 +1,148 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::*;
+use crate::forward::forward_process_traits;
+
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, capacity: f64) -> Self {
+        assert!(rate > 0.0, "rate must be greater than zero");
+        Self { rate, capacity, tokens: capacity, last: Instant::now() }
+    }
+
+    /// Takes n tokens from the bucket and returns how long the caller has to wait
+    /// before the bucket is out of debt
+    fn take(&mut self, n: f64) -> Duration {
+        self.take_at(n, Instant::now())
+    }
+
+    /// Takes n tokens from the bucket at the given time
+    fn take_at(&mut self, n: f64, now: Instant) -> Duration {
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+
+        self.tokens -= n;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Wraps a memory backend and limits the number of calls and bytes per second using a token
+/// bucket. Calls over the limit block until enough tokens are available. Reads and writes
+/// share the same limits.
+pub struct Throttled<M> {
+    inner: M,
+    calls: Option<Mutex<TokenBucket>>,
+    bytes: Option<Mutex<TokenBucket>>,
+}
+
+impl<M> Throttled<M> {
+    /// Creates a new wrapper without any limits
+    pub fn new(inner: M) -> Self {
+        Self { inner, calls: None, bytes: None }
+    }
+
+    /// Limits calls to rate per second, allowing bursts of up to burst calls
+    pub fn calls_per_second(mut self, rate: f64, burst: f64) -> Self {
+        self.calls = Some(Mutex::new(TokenBucket::new(rate, burst)));
+        self
+    }
+
+    /// Limits the bytes read and written to rate per second, allowing bursts of up to burst bytes
+    pub fn bytes_per_second(mut self, rate: f64, burst: f64) -> Self {
+        self.bytes = Some(Mutex::new(TokenBucket::new(rate, burst)));
+        self
+    }
+
+    /// Returns a reference to the inner type
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    /// Consumes the wrapper and returns the inner type
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    /// Blocks until a call of len bytes is allowed
+    fn acquire(&self, len: usize) {
+        let call_wait = self.calls.as_ref().map_or(Duration::ZERO, |b| b.lock().unwrap().take(1.0));
+        let byte_wait = self.bytes.as_ref().map_or(Duration::ZERO, |b| b.lock().unwrap().take(len as f64));
+
+        let wait = call_wait.max(byte_wait);
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+impl<M: MemoryRead> MemoryRead for Throttled<M> {
+    fn try_read_bytes_into(&self, address: u64, buffer: &mut [u8]) -> Option<()> {
+        self.acquire(buffer.len());
+        self.inner.try_read_bytes_into(address, buffer)
+    }
+}
+
+impl<M: MemoryWrite> MemoryWrite for Throttled<M> {
+    fn try_write_bytes(&self, address: u64, buffer: &[u8]) -> Option<()> {
+        self.acquire(buffer.len());
+        self.inner.try_write_bytes(address, buffer)
+    }
+}
+
+forward_process_traits!(Throttled.inner: ModuleList, ProcessInfo, MemoryAllocate, MemoryProtect, MemoryRegions);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer() -> MemoryBuffer<Vec<u8>> {
+        MemoryBuffer::new(0, vec![0u8; 0x1000])
+    }
+
+    #[test]
+    fn test_call_limit() {
+        let throttled = Throttled::new(buffer()).calls_per_second(100.0, 1.0);
+        let start = Instant::now();
+        for _ in 0..6 {
+            throttled.read::<u8>(0);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(45), "{:?}", start.elapsed());
+    }
+
+    #[test]
+    fn test_byte_limit() {
+        let throttled = Throttled::new(buffer()).bytes_per_second(0x1000 as f64, 0x100 as f64);
+        let start = Instant::now();
+        throttled.try_read_bytes(0, 0x100).unwrap();
+
+        // the second read has to wait for 0x100 bytes worth of tokens
+        throttled.try_write_bytes(0, &[0u8; 0x100]).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(55), "{:?}", start.elapsed());
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(100.0, 2.0);
+        let start = bucket.last;
+
+        assert_eq!(bucket.take_at(1.0, start), Duration::ZERO);
+        assert_eq!(bucket.take_at(1.0, start), Duration::ZERO);
+        assert_eq!(bucket.take_at(1.0, start), Duration::from_millis(10));
+
+        // the debt is paid off after 10ms and the bucket refills up to its capacity
+        assert_eq!(bucket.take_at(1.0, start + Duration::from_millis(20)), Duration::ZERO);
+        assert_eq!(bucket.take_at(2.0, start + Duration::from_secs(1)), Duration::ZERO);
+        assert_eq!(bucket.take_at(1.0, start + Duration::from_secs(1)), Duration::from_millis(10));
+    }
+}